@@ -21,6 +21,7 @@ impl FromStr for Channel {
         let mut parts = s.splitn(2, '-');
         let channel = parts
             .next()
+            .filter(|channel| !channel.is_empty())
             .ok_or_else(|| anyhow::anyhow!("missing channel"))?;
         let channel = ChannelKind::from_str(channel)?;
         let host = parts
@@ -154,6 +155,7 @@ mod test {
     #[test]
     fn test_malformed() {
         assert!("".parse::<super::Channel>().is_err());
+        assert!("-linux-x86_64".parse::<super::Channel>().is_err());
         assert!("latest-".parse::<super::Channel>().is_err());
         assert!("latest-linux".parse::<super::Channel>().is_err());
     }
@@ -1,12 +1,32 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
+use anyhow::Context;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
+use crate::channel::{Channel, ChannelKind};
+
+mod jsonc;
+mod layer;
+mod schema;
 mod util;
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub use layer::{config_layers, merge_layers, merged_config, LayerKind};
+pub use schema::config_schema;
+
+/// The version of the config layout understood by this lunik.
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// Version of the config layout. Configs without it are version 0.
+    #[serde(default)]
+    pub version: u32,
+
     /// Toolchain information
     #[serde(default)]
     pub toolchain: HashMap<String, ToolchainInfo>,
@@ -16,7 +36,40 @@ pub struct Config {
     pub channels: HashMap<String, ChannelInfo>,
 
     /// Default toolchain
+    #[serde(default)]
     pub default: String,
+
+    /// Toolchain aliases, like `stable` pointing at a channel toolchain
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+
+    /// Read-only directories containing toolchains, like a system-wide
+    /// install. They are searched after the user toolchain root.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub toolchain_stores: Vec<PathBuf>,
+
+    /// Directory of the lunik executable and the tool shims, if not `~/.moon/bin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bin_dir: Option<PathBuf>,
+
+    /// Unknown fields, kept so that they survive saving the config
+    #[serde(flatten)]
+    pub extra: serde_json_lenient::Map<String, serde_json_lenient::Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            toolchain: Default::default(),
+            channels: Default::default(),
+            default: Default::default(),
+            aliases: Default::default(),
+            toolchain_stores: Default::default(),
+            bin_dir: Default::default(),
+            extra: Default::default(),
+        }
+    }
 }
 
 impl Config {
@@ -26,6 +79,134 @@ impl Config {
     ) -> util::ConfigToolchainFallbackIter<'a> {
         util::ConfigToolchainFallbackIter::new(self, toolchain_name)
     }
+
+    /// Find the directory of a toolchain: its `root_path` if set, otherwise its
+    /// directory in the user toolchain root or in one of the toolchain stores.
+    /// New toolchains are installed to the user toolchain root.
+    pub fn toolchain_dir(&self, name: &str) -> PathBuf {
+        if let Some(root) = self
+            .toolchain
+            .get(name)
+            .and_then(|info| info.root_path.as_ref())
+        {
+            return expand_config_path(root);
+        }
+        let user_dir = toolchain_path(name);
+        if user_dir.exists() {
+            return user_dir;
+        }
+        self.toolchain_stores
+            .iter()
+            .map(|store| expand_config_path(store).join(name))
+            .find(|dir| dir.is_dir())
+            .unwrap_or(user_dir)
+    }
+
+    /// The read-only toolchain store containing the toolchain, if any.
+    pub fn toolchain_store(&self, name: &str) -> Option<PathBuf> {
+        let dir = self.toolchain_dir(name);
+        self.toolchain_stores
+            .iter()
+            .map(|store| expand_config_path(store))
+            .find(|store| dir.starts_with(store))
+    }
+
+    /// Check whether `name` refers to a toolchain in the config, either
+    /// directly, by an alias or by its channel name.
+    pub fn contains_toolchain(&self, name: &str) -> bool {
+        self.toolchain_fallback_iter(name).next().is_some()
+    }
+
+    /// Check that all toolchain references in the config are valid.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.version != CONFIG_VERSION {
+            anyhow::bail!(
+                "Config version must be {}, found {}",
+                CONFIG_VERSION,
+                self.version
+            );
+        }
+
+        if !self.default.is_empty() && !self.contains_toolchain(&self.default) {
+            anyhow::bail!("Default toolchain `{}` does not exist", self.default);
+        }
+
+        let mut names = self.toolchain.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let info = &self.toolchain[name];
+            if let Some(fallback) = &info.fallback {
+                if !self.contains_toolchain(fallback) {
+                    anyhow::bail!(
+                        "Fallback `{}` of toolchain `{}` does not exist",
+                        fallback,
+                        name
+                    );
+                }
+            }
+
+            let mut aliases = info.aliases.iter().collect::<Vec<_>>();
+            aliases.sort();
+            for (alias, target) in aliases {
+                if target.is_empty() {
+                    anyhow::bail!(
+                        "Alias `{}` of toolchain `{}` does not name a tool",
+                        alias,
+                        name
+                    );
+                }
+            }
+
+            let mut visited = vec![];
+            for (toolchain, _) in self.toolchain_fallback_iter(name) {
+                if visited.contains(&toolchain) {
+                    anyhow::bail!(
+                        "The fallbacks of toolchain `{}` form a cycle through `{}`",
+                        name,
+                        toolchain
+                    );
+                }
+                visited.push(toolchain);
+            }
+        }
+
+        let mut aliases = self.aliases.iter().collect::<Vec<_>>();
+        aliases.sort();
+        for (alias, target) in aliases {
+            if alias.is_empty() {
+                anyhow::bail!("Alias names must not be empty");
+            }
+            if self.toolchain.contains_key(alias) {
+                anyhow::bail!("Alias `{}` has the same name as a toolchain", alias);
+            }
+            // Channel names are resolved after aliases, so an alias would hide them
+            let shadows_channel = alias.parse::<Channel>().is_ok_and(|channel| {
+                !matches!(channel.channel, ChannelKind::Version(_))
+                    || self.toolchain.contains_key(&channel.to_string())
+            });
+            if shadows_channel {
+                anyhow::bail!("Alias `{}` has the name of a release channel", alias);
+            }
+            if self.aliases.contains_key(target) {
+                anyhow::bail!(
+                    "Alias `{}` points at another alias `{}`, which is not supported",
+                    alias,
+                    target
+                );
+            }
+            if !self.contains_toolchain(target) {
+                anyhow::bail!("Toolchain `{}` of alias `{}` does not exist", target, alias);
+            }
+        }
+
+        for name in self.channels.keys() {
+            if !self.toolchain.contains_key(name) {
+                anyhow::bail!("Channel `{}` has no toolchain entry", name);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -47,12 +228,34 @@ pub struct ToolchainInfo {
 
     /// Override path for core library
     pub core_path: Option<PathBuf>,
+
+    /// Extra environment variables for running the tools. `$VAR` and `${VAR}`
+    /// in the values are expanded from the current environment.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+
+    /// Arguments added before the user's arguments, by tool name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub args_prefix: HashMap<String, Vec<String>>,
+
+    /// Tool aliases. The first element is the tool to run, and the rest are
+    /// arguments added before the user's arguments.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, Vec<String>>,
+
+    /// Unknown fields, kept so that they survive saving the config
+    #[serde(flatten)]
+    pub extra: serde_json_lenient::Map<String, serde_json_lenient::Value>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct ChannelInfo {
     /// Override URL
     url: Option<String>,
+
+    /// Unknown fields, kept so that they survive saving the config
+    #[serde(flatten)]
+    pub extra: serde_json_lenient::Map<String, serde_json_lenient::Value>,
 }
 
 pub const MOON_HOME_DEFAULT: &str = ".moon";
@@ -61,6 +264,7 @@ pub const TOOLCHAIN_DEFAULT_ROOT: &str = "toolchain";
 pub const CONFIG_NAME: &str = "lunik.json";
 
 pub const LUNIK_HOME_ENV_NAME: &str = "LUNIK_HOME";
+pub const LUNIK_BIN_DIR_ENV_NAME: &str = "LUNIK_BIN_DIR";
 pub const MOON_HOME_ENV_NAME: &str = "MOON_HOME";
 pub const MOON_CORE_OVERRIDE_ENV_NAME: &str = "MOON_CORE_OVERRIDE";
 
@@ -86,8 +290,90 @@ pub fn home_dir() -> PathBuf {
     HOME_DIR_CACHE.clone()
 }
 
+static BIN_DIR_CACHE: Lazy<PathBuf> = Lazy::new(|| match read_config() {
+    Ok(cfg) => config_bin_dir(&cfg),
+    Err(_) => config_bin_dir(&Config::default()),
+});
+
+/// Find the directory of the shims for the given config.
+///
+/// 1. Try the environment variable `LUNIK_BIN_DIR`.
+/// 2. If not set, use `bin_dir` in the config.
+/// 3. If not set either, use `{HOME_DIR}/bin`.
+pub fn config_bin_dir(cfg: &Config) -> PathBuf {
+    match std::env::var_os(LUNIK_BIN_DIR_ENV_NAME) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => cfg
+            .bin_dir
+            .as_ref()
+            .map(|dir| expand_config_path(dir))
+            .unwrap_or_else(|| home_dir().join(BIN_DIR)),
+    }
+}
+
+/// Find the directory of the shims.
 pub fn moon_bin_dir() -> PathBuf {
-    home_dir().join(BIN_DIR)
+    BIN_DIR_CACHE.clone()
+}
+
+/// Expand `~`, `$HOME`, `$MOON_HOME` and `$LUNIK_HOME` (also written as
+/// `${VAR}`) in a path from the config, and resolve it against `base_dir` if it
+/// is relative. `MOON_HOME` and `LUNIK_HOME` both mean the lunik home directory.
+pub fn expand_path(path: &str, base_dir: Option<&Path>) -> PathBuf {
+    let user_home = home::home_dir().unwrap_or_default().display().to_string();
+    let lunik_home = home_dir().display().to_string();
+
+    let expanded = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            format!("{}{}", user_home, rest)
+        }
+        _ => path.to_string(),
+    };
+    let expanded = expand_vars(&expanded, |name| match name {
+        "HOME" => Some(user_home.clone()),
+        MOON_HOME_ENV_NAME | LUNIK_HOME_ENV_NAME => Some(lunik_home.clone()),
+        _ => None,
+    });
+
+    let expanded = PathBuf::from(expanded);
+    match base_dir {
+        Some(base_dir) if expanded.is_relative() => base_dir.join(expanded),
+        _ => expanded,
+    }
+}
+
+/// Expand a path of the merged config. Paths from the user config are only
+/// expanded here, relative to the user config file.
+pub fn expand_config_path(path: &Path) -> PathBuf {
+    expand_path(&path.to_string_lossy(), Some(&home_dir()))
+}
+
+/// Expand `$VAR` and `${VAR}` in `value` with the values from `get_var`.
+/// Unknown variables are kept as written.
+pub fn expand_vars(value: &str, get_var: impl Fn(&str) -> Option<String>) -> String {
+    let is_var_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, next) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", after),
+            }
+        } else {
+            let end = after.find(|c| !is_var_char(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        match get_var(name).filter(|_| !name.is_empty()) {
+            Some(var) => out.push_str(&var),
+            None => out.push_str(&rest[i..rest.len() - next.len()]),
+        }
+        rest = next;
+    }
+    out.push_str(rest);
+    out
 }
 
 /// Find the resource dir for Lunik
@@ -110,15 +396,283 @@ pub fn toolchain_path(toolchain_name: &str) -> PathBuf {
         .join(toolchain_name)
 }
 
+/// Migrations of the config layout. The migration at index `i` upgrades a
+/// config from version `i` to `i + 1`.
+const MIGRATIONS: &[fn(&mut serde_json_lenient::Value)] = &[
+    // 0 -> 1: only adds the version field
+    |_| {},
+];
+const _: () = assert!(MIGRATIONS.len() == CONFIG_VERSION as usize);
+
+/// Upgrade a config to [`CONFIG_VERSION`]. Returns the original version if it
+/// was migrated.
+fn migrate_config(value: &mut serde_json_lenient::Value) -> anyhow::Result<Option<u32>> {
+    let version = match value.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid config version: {}", v))?,
+    };
+
+    if version > CONFIG_VERSION {
+        anyhow::bail!(
+            "The config is of version {}, but this lunik only understands up to version {}. \
+            Please upgrade lunik.",
+            version,
+            CONFIG_VERSION
+        );
+    }
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    if !value.is_object() {
+        anyhow::bail!("The config is not an object");
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(value);
+    }
+    value["version"] = CONFIG_VERSION.into();
+    Ok(Some(version))
+}
+
+/// Read the config merged from all layers: the system config, the user config,
+/// the project config and the environment, in increasing precedence.
+///
+/// Use [`read_user_config`] to get a config to modify and save.
 pub fn read_config() -> anyhow::Result<Config> {
+    merged_config(&config_layers(None)?)
+}
+
+/// Describe an error in a config file with its position and the field it is in.
+fn config_error(path: &Path, text: &str, e: &serde_json_lenient::Error) -> anyhow::Error {
+    let msg = e.to_string();
+    let msg = msg
+        .rsplit_once(" at line ")
+        .map_or(msg.as_str(), |(msg, _)| msg);
+    let field = jsonc::path_at(text, jsonc::offset_of(text, e.line(), e.column()));
+    let location = format!("{}:{}:{}", path.display(), e.line(), e.column());
+    if field.is_empty() {
+        anyhow::anyhow!("Invalid config {}: {}", location, msg)
+    } else {
+        anyhow::anyhow!(
+            "Invalid config {}: {}, in `{}`",
+            location,
+            msg,
+            field.join(".")
+        )
+    }
+}
+
+/// Parse and migrate a config file, and check the types of its fields.
+fn parse_config_file(
+    path: &Path,
+    text: &str,
+) -> anyhow::Result<(serde_json_lenient::Value, Option<u32>)> {
+    let mut value: serde_json_lenient::Value =
+        serde_json_lenient::from_str(text).map_err(|e| config_error(path, text, &e))?;
+    let old_version =
+        migrate_config(&mut value).with_context(|| format!("In {}", path.display()))?;
+
+    if let Err(e) = serde_json_lenient::from_value::<Config>(value.clone()) {
+        // Parse the text again to find where the error is
+        return Err(match serde_json_lenient::from_str::<Config>(text) {
+            Err(e) if e.line() > 0 => config_error(path, text, &e),
+            _ => anyhow::anyhow!("Invalid config {}: {}", path.display(), e),
+        });
+    }
+    Ok((value, old_version))
+}
+
+fn config_not_found(path: &Path) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Config file not found at {}. Run `lunik init-config` to create it.",
+        path.display()
+    )
+}
+
+/// Read the user config, which is the one lunik writes to.
+pub fn read_user_config() -> anyhow::Result<Config> {
     let config_path = config_path();
-    let cfg: Config = serde_json_lenient::from_reader(std::fs::File::open(config_path)?)?;
+    let text = match std::fs::read_to_string(&config_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(config_not_found(&config_path))
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", config_path.display()))
+        }
+    };
+    let (value, old_version) = parse_config_file(&config_path, &text)?;
+
+    if let Some(old_version) = old_version {
+        let backup = config_path.with_extension(format!("v{}.json", old_version));
+        std::fs::copy(&config_path, &backup)
+            .with_context(|| format!("Failed to back up config to {}", backup.display()))?;
+        tracing::info!(
+            "Migrated config from version {} to {}; the old config is saved at {}",
+            old_version,
+            CONFIG_VERSION,
+            backup.display()
+        );
+        let cfg: Config = serde_json_lenient::from_value(value)?;
+        save_config(&cfg)?;
+        return Ok(cfg);
+    }
+
+    let cfg: Config = serde_json_lenient::from_value(value)?;
     Ok(cfg)
 }
 
+/// Save the config. If the file already exists, only the changed parts are
+/// rewritten, keeping comments and formatting.
 pub fn save_config(cfg: &Config) -> anyhow::Result<()> {
     let config_path = config_path();
-    let mut file = std::fs::File::create(config_path)?;
-    serde_json_lenient::to_writer_pretty(&mut file, cfg)?;
+    let new = serde_json_lenient::to_value(cfg)?;
+    let patched =
+        std::fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|original| match jsonc::patch(&original, &new) {
+                Ok(patched) => Some(patched),
+                Err(e) => {
+                    tracing::warn!("Unable to keep the formatting of the config: {}", e);
+                    None
+                }
+            });
+    let content = match patched {
+        Some(content) => content,
+        None => serde_json_lenient::to_string_pretty(&new)?,
+    };
+    write_atomically(&config_path, content.as_bytes())
+        .with_context(|| format!("Failed to write {}", config_path.display()))?;
+    Ok(())
+}
+
+/// Write a file through a temporary file and a rename, so that concurrent
+/// readers never see it half written.
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    // Write through symlinks, like dotfile managers create, instead of replacing them
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(content)?;
+    if let Ok(metadata) = std::fs::metadata(&path) {
+        file.as_file().set_permissions(metadata.permissions())?;
+    }
+    file.persist(&path).map_err(|e| e.error)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_path() {
+        let user_home = home::home_dir().unwrap_or_default();
+        assert_eq!(expand_path("~/moon", None), user_home.join("moon"));
+        assert_eq!(expand_path("$HOME/moon", None), user_home.join("moon"));
+        assert_eq!(
+            expand_path("${LUNIK_HOME}/toolchain", None),
+            home_dir().join("toolchain")
+        );
+        assert_eq!(expand_path("$MOON_HOME/lib", None), home_dir().join("lib"));
+        assert_eq!(
+            expand_path("$HOMEBREW_PREFIX/bin", None),
+            Path::new("$HOMEBREW_PREFIX/bin")
+        );
+        assert_eq!(
+            expand_path("dev/bin", Some(Path::new("/etc/lunik"))),
+            Path::new("/etc/lunik/dev/bin")
+        );
+        assert_eq!(
+            expand_path("/opt/~x", Some(Path::new("/etc/lunik"))),
+            Path::new("/opt/~x")
+        );
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let get_var = |name: &str| match name {
+            "PATH" => Some("/usr/bin".to_string()),
+            "HOME" => Some("/home/me".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_vars("/opt/cc/bin:$PATH", get_var),
+            "/opt/cc/bin:/usr/bin"
+        );
+        assert_eq!(expand_vars("${HOME}/x", get_var), "/home/me/x");
+        assert_eq!(expand_vars("$HOMEBREW/x", get_var), "$HOMEBREW/x");
+        assert_eq!(expand_vars("a${UNKNOWN}-b", get_var), "a${UNKNOWN}-b");
+        assert_eq!(expand_vars("5$ and ${", get_var), "5$ and ${");
+    }
+
+    #[test]
+    fn test_validate_aliases() {
+        let mut config = Config::default();
+        config
+            .toolchain
+            .insert("dev".into(), ToolchainInfo::default());
+        config.aliases.insert("stable".into(), "dev".into());
+        config.default = "stable".into();
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config
+                .toolchain_fallback_iter("stable")
+                .map(|(name, _)| name.into_owned())
+                .collect::<Vec<_>>(),
+            ["dev"]
+        );
+
+        config.aliases.insert("lts".into(), "stable".into());
+        assert!(config.validate().is_err());
+        config.aliases.remove("lts");
+
+        config.aliases.insert("dev".into(), "stable".into());
+        assert!(config.validate().is_err());
+        config.aliases.remove("dev");
+
+        config.aliases.insert("lts".into(), "missing".into());
+        assert!(config.validate().is_err());
+        config.aliases.remove("lts");
+
+        // Aliases must not hide release channels
+        config
+            .toolchain
+            .insert("0.1.0-linux-x86_64".into(), ToolchainInfo::default());
+        for name in [
+            "latest",
+            "bleeding",
+            "latest-linux-x86_64",
+            "0.1.0-linux-x86_64",
+        ] {
+            config.aliases.insert(name.into(), "dev".into());
+            assert!(config.validate().is_err(), "{}", name);
+            config.aliases.remove(name);
+        }
+        config.aliases.insert("0.2.0".into(), "dev".into());
+        assert!(config.validate().is_ok());
+
+        // An empty name is neither an alias nor a channel
+        config.aliases.insert("".into(), "dev".into());
+        assert!(config.validate().is_err());
+        config.aliases.remove("");
+        assert!(crate::mux::real_toolchain_name(&config, "").is_err());
+    }
+
+    #[test]
+    fn test_migrate_config() {
+        let mut value = serde_json_lenient::json!({ "toolchain": {}, "default": "" });
+        assert_eq!(migrate_config(&mut value).unwrap(), Some(0));
+        assert_eq!(value["version"], CONFIG_VERSION);
+        assert_eq!(migrate_config(&mut value).unwrap(), None);
+
+        let mut value = serde_json_lenient::json!({ "version": CONFIG_VERSION + 1 });
+        assert!(migrate_config(&mut value).is_err());
+
+        let mut value = serde_json_lenient::json!({ "version": "1" });
+        assert!(migrate_config(&mut value).is_err());
+    }
+}
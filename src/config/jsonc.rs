@@ -0,0 +1,580 @@
+//! Editing JSON with comments in place, so that the comments and formatting
+//! of a hand-written config survive when lunik saves it.
+
+use serde_json_lenient::Value;
+
+/// A JSON value in the source text.
+#[derive(Debug)]
+enum Node {
+    Object {
+        /// Position right after the opening brace
+        open_end: usize,
+        members: Vec<Member>,
+    },
+    /// Any other value, which is always replaced as a whole
+    Other,
+}
+
+#[derive(Debug)]
+struct Member {
+    key: String,
+    key_start: usize,
+    value: Node,
+    value_start: usize,
+    value_end: usize,
+    /// Position right after the comma following the value, if any
+    comma_end: Option<usize>,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn error<T>(&self, msg: &str) -> anyhow::Result<T> {
+        anyhow::bail!("{} at byte {}", msg, self.pos)
+    }
+
+    /// Skip whitespace and comments.
+    fn skip_trivia(&mut self) -> anyhow::Result<()> {
+        loop {
+            let rest = &self.text[self.pos..];
+            if rest.starts_with("//") {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let Some(end) = comment.find("*/") else {
+                    return self.error("Unterminated block comment");
+                };
+                self.pos += end + 4;
+            } else if let Some(c) = self.peek().filter(|c| c.is_ascii_whitespace()) {
+                self.pos += (c as char).len_utf8();
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> anyhow::Result<()> {
+        self.skip_trivia()?;
+        if self.peek() != Some(c) {
+            return self.error(&format!("Expected `{}`", c as char));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Parse a value, returning it with its start and end position.
+    fn value(&mut self) -> anyhow::Result<(Node, usize, usize)> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        let node = match self.peek() {
+            Some(b'{') => self.object()?,
+            Some(b'[') => {
+                self.array()?;
+                Node::Other
+            }
+            Some(b'"') => {
+                self.string()?;
+                Node::Other
+            }
+            Some(_) => {
+                let rest = &self.text[self.pos..];
+                let len = rest
+                    .find(|c: char| c.is_ascii_whitespace() || ",:]}/".contains(c))
+                    .unwrap_or(rest.len());
+                if len == 0 {
+                    return self.error("Expected a value");
+                }
+                self.pos += len;
+                Node::Other
+            }
+            None => return self.error("Unexpected end of input"),
+        };
+        Ok((node, start, self.pos))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'\\') => self.pos += 2,
+                Some(b'"') => break,
+                Some(_) => self.pos += 1,
+                None => return self.error("Unterminated string"),
+            }
+        }
+        self.pos += 1;
+        Ok(serde_json_lenient::from_str(&self.text[start..self.pos])?)
+    }
+
+    fn array(&mut self) -> anyhow::Result<()> {
+        self.pos += 1;
+        loop {
+            self.skip_trivia()?;
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(());
+            }
+            self.value()?;
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {}
+                _ => return self.error("Expected `,` or `]`"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> anyhow::Result<Node> {
+        self.pos += 1;
+        let open_end = self.pos;
+        let mut members = vec![];
+        loop {
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Node::Object { open_end, members });
+                }
+                Some(b'"') => {}
+                _ => return self.error("Expected a key or `}`"),
+            }
+            let key_start = self.pos;
+            let key = self.string()?;
+            self.expect(b':')?;
+            let (value, value_start, value_end) = self.value()?;
+            self.skip_trivia()?;
+            let comma_end = match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    Some(self.pos)
+                }
+                Some(b'}') => None,
+                _ => return self.error("Expected `,` or `}`"),
+            };
+            members.push(Member {
+                key,
+                key_start,
+                value,
+                value_start,
+                value_end,
+                comma_end,
+            });
+        }
+    }
+}
+
+/// The indentation of the line containing `pos`.
+fn indent_at(text: &str, pos: usize) -> &str {
+    let line_start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Remove the null members of objects, as they are the same as absent ones.
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Format a value as pretty JSON, with all lines but the first indented.
+fn format_value(value: &Value, indent: &str) -> String {
+    serde_json_lenient::to_string_pretty(&without_nulls(value))
+        .expect("values are always serializable")
+        .replace('\n', &format!("\n{}", indent))
+}
+
+/// A replacement of `text[start..end]`.
+struct Edit {
+    start: usize,
+    end: usize,
+    replacement: String,
+}
+
+fn diff(
+    text: &str,
+    node: &Node,
+    span: (usize, usize),
+    old: &Value,
+    new: &Value,
+    edits: &mut Vec<Edit>,
+) {
+    if old == new {
+        return;
+    }
+    let (Node::Object { open_end, members }, Some(old_map), Some(new_map)) =
+        (node, old.as_object(), new.as_object())
+    else {
+        edits.push(Edit {
+            start: span.0,
+            end: span.1,
+            replacement: format_value(new, indent_at(text, span.0)),
+        });
+        return;
+    };
+
+    // Update or remove existing members. A removed member takes the text
+    // from the end of the previous line comment to its own comma, including
+    // its comments, so that the comment of the previous member stays.
+    let mut last_kept: Option<&Member> = None;
+    let mut removed_after_kept = false;
+    let mut prev_end = *open_end + line_comment_len(&text[*open_end..]);
+    for member in members {
+        let end = member.comma_end.unwrap_or(member.value_end);
+        let end = end + line_comment_len(&text[end..]);
+        // Absent and null values mean the same in the config
+        match new_map.get(&member.key).filter(|v| !v.is_null()) {
+            Some(new_value) => {
+                let old_value = old_map.get(&member.key).unwrap_or(&Value::Null);
+                diff(
+                    text,
+                    &member.value,
+                    (member.value_start, member.value_end),
+                    old_value,
+                    new_value,
+                    edits,
+                );
+                last_kept = Some(member);
+                removed_after_kept = false;
+            }
+            None => {
+                edits.push(Edit {
+                    start: prev_end,
+                    end,
+                    replacement: String::new(),
+                });
+                removed_after_kept = true;
+            }
+        }
+        prev_end = end;
+    }
+
+    // Append new members. Absent and null values mean the same in the config.
+    let added = new_map
+        .iter()
+        .filter(|(k, v)| !v.is_null() && !members.iter().any(|m| &m.key == *k))
+        .map(|(k, v)| (serde_json_lenient::to_string(k).unwrap(), v))
+        .collect::<Vec<_>>();
+    if added.is_empty() {
+        // Drop the comma left after the new last member, unless the object
+        // has a trailing comma anyway
+        let had_trailing_comma = members.last().is_some_and(|m| m.comma_end.is_some());
+        if let Some(comma_end) = last_kept
+            .and_then(|m| m.comma_end)
+            .filter(|_| removed_after_kept && !had_trailing_comma)
+        {
+            edits.push(Edit {
+                start: comma_end - 1,
+                end: comma_end,
+                replacement: String::new(),
+            });
+        }
+        return;
+    }
+
+    // Objects written on a single line get the new members on the same line
+    let inline = members
+        .first()
+        .is_some_and(|m| !text[*open_end..m.key_start].contains('\n'));
+    if inline {
+        let (pos, mut replacement) = match last_kept {
+            Some(m) if m.comma_end.is_none() => (m.value_end, ", ".to_string()),
+            Some(m) => (m.comma_end.unwrap(), " ".to_string()),
+            None => (*open_end, " ".to_string()),
+        };
+        let added = added
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k, without_nulls(v)))
+            .collect::<Vec<_>>();
+        replacement.push_str(&added.join(", "));
+        edits.push(Edit {
+            start: pos,
+            end: pos,
+            replacement,
+        });
+        return;
+    }
+
+    let outer = indent_at(text, span.0);
+    let inner = match members.first() {
+        Some(m) => indent_at(text, m.key_start).to_string(),
+        None => format!("{}  ", outer),
+    };
+    let pos = match last_kept {
+        Some(m) => {
+            let end = match m.comma_end {
+                Some(end) => end,
+                None => {
+                    edits.push(Edit {
+                        start: m.value_end,
+                        end: m.value_end,
+                        replacement: ",".into(),
+                    });
+                    m.value_end
+                }
+            };
+            // Keep a comment at the end of the line with the member it belongs to
+            end + line_comment_len(&text[end..])
+        }
+        None => *open_end,
+    };
+    let mut replacement = String::new();
+    for (i, (key, value)) in added.iter().enumerate() {
+        if i > 0 {
+            replacement.push(',');
+        }
+        replacement.push('\n');
+        replacement.push_str(&inner);
+        replacement.push_str(key);
+        replacement.push_str(": ");
+        replacement.push_str(&format_value(value, &inner));
+    }
+    if members.is_empty() {
+        replacement.push('\n');
+        replacement.push_str(outer);
+    }
+    edits.push(Edit {
+        start: pos,
+        end: pos,
+        replacement,
+    });
+}
+
+/// The length of the spaces and line comment at the start of `text`, if the
+/// line ends with a comment.
+fn line_comment_len(text: &str) -> usize {
+    let trimmed = text.trim_start_matches([' ', '\t']);
+    if trimmed.starts_with("//") {
+        text.len() - trimmed.len() + trimmed.find('\n').unwrap_or(trimmed.len())
+    } else {
+        0
+    }
+}
+
+/// Convert a 1-based line and column to a byte offset in `text`.
+pub fn offset_of(text: &str, line: usize, column: usize) -> usize {
+    let line_start = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>();
+    (line_start + column.saturating_sub(1)).min(text.len())
+}
+
+/// Find the path of keys leading to the value at `offset`. Returns an empty
+/// path if the text cannot be parsed.
+pub fn path_at(text: &str, offset: usize) -> Vec<String> {
+    let mut parser = Parser { text, pos: 0 };
+    let Ok((mut node, _, _)) = parser.value() else {
+        return vec![];
+    };
+    let mut path = vec![];
+    while let Node::Object { members, .. } = node {
+        let Some(member) = members
+            .into_iter()
+            .find(|m| m.key_start <= offset && offset <= m.value_end)
+        else {
+            break;
+        };
+        path.push(member.key);
+        node = member.value;
+    }
+    path
+}
+
+/// Rewrite `text` so that it represents `new`, changing only the parts that
+/// differ and keeping comments and formatting elsewhere.
+pub fn patch(text: &str, new: &Value) -> anyhow::Result<String> {
+    let old: Value = serde_json_lenient::from_str(text)?;
+    let mut parser = Parser { text, pos: 0 };
+    let (node, start, end) = parser.value()?;
+
+    let mut edits = vec![];
+    diff(text, &node, (start, end), &old, new, &mut edits);
+    // Insertions come before removals starting at the same position
+    edits.sort_by_key(|e| (e.start, e.end));
+
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    for edit in edits {
+        out.push_str(&text[pos..edit.start]);
+        out.push_str(&edit.replacement);
+        pos = edit.end;
+    }
+    out.push_str(&text[pos..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json_lenient::json;
+
+    const CONFIG: &str = r#"{
+  // My toolchains
+  "toolchain": {
+    "dev": {
+      "root_path": "/opt/dev", // built locally
+      "fallback": "stable",
+    },
+    "stable": {}
+  },
+  /* set by lunik */
+  "default": "stable",
+  "my_note": 1
+}
+"#;
+
+    fn check(new: Value, expected: &str) {
+        let patched = patch(CONFIG, &new).unwrap();
+        assert_eq!(patched, expected);
+        let reparsed: Value = serde_json_lenient::from_str(&patched).unwrap();
+        assert_eq!(reparsed, new);
+    }
+
+    #[test]
+    fn test_patch_unchanged() {
+        let old: Value = serde_json_lenient::from_str(CONFIG).unwrap();
+        assert_eq!(patch(CONFIG, &old).unwrap(), CONFIG);
+    }
+
+    #[test]
+    fn test_patch_replace() {
+        let mut new: Value = serde_json_lenient::from_str(CONFIG).unwrap();
+        new["default"] = json!("dev");
+        check(
+            new,
+            &CONFIG.replace(r#""default": "stable""#, r#""default": "dev""#),
+        );
+    }
+
+    #[test]
+    fn test_patch_remove() {
+        let mut new: Value = serde_json_lenient::from_str(CONFIG).unwrap();
+        new["toolchain"]["dev"]
+            .as_object_mut()
+            .unwrap()
+            .remove("fallback");
+        new.as_object_mut().unwrap().remove("my_note");
+        check(
+            new,
+            r#"{
+  // My toolchains
+  "toolchain": {
+    "dev": {
+      "root_path": "/opt/dev", // built locally
+    },
+    "stable": {}
+  },
+  /* set by lunik */
+  "default": "stable"
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn test_patch_remove_style() {
+        let text = r#"{
+  "a": 1, // first
+  "b": 2, // second
+  "c": { "x": 1, "y": 2 }
+}"#;
+        let new = json!({ "a": 1, "c": { "x": 1 } });
+        assert_eq!(
+            patch(text, &new).unwrap(),
+            r#"{
+  "a": 1, // first
+  "c": { "x": 1 }
+}"#
+        );
+
+        let new = json!({ "b": 2, "c": null });
+        assert_eq!(
+            patch(text, &new).unwrap(),
+            r#"{
+  "b": 2 // second
+}"#
+        );
+    }
+
+    #[test]
+    fn test_patch_insert() {
+        let mut new: Value = serde_json_lenient::from_str(CONFIG).unwrap();
+        new["toolchain"]["stable"]["root_path"] = json!("/opt/stable");
+        new["toolchain"]["stable"]["fallback"] = Value::Null;
+        new["toolchain"]["nightly"] = json!({ "fallback": "dev" });
+        new["version"] = json!(1);
+        // Null values are not inserted
+        assert_eq!(
+            patch(CONFIG, &new).unwrap(),
+            r#"{
+  // My toolchains
+  "toolchain": {
+    "dev": {
+      "root_path": "/opt/dev", // built locally
+      "fallback": "stable",
+    },
+    "stable": {
+      "root_path": "/opt/stable"
+    },
+    "nightly": {
+      "fallback": "dev"
+    }
+  },
+  /* set by lunik */
+  "default": "stable",
+  "my_note": 1,
+  "version": 1
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_patch_insert_style() {
+        let text = r#"{
+  "toolchain": {
+    "dev": { "root_path": "/opt/dev" } // local build
+  }
+}"#;
+        let new = json!({
+            "toolchain": {
+                "dev": { "root_path": "/opt/dev", "fallback": "stable" },
+                "stable": { "root_path": "/opt/stable", "core_path": null }
+            }
+        });
+        assert_eq!(
+            patch(text, &new).unwrap(),
+            r#"{
+  "toolchain": {
+    "dev": { "root_path": "/opt/dev", "fallback": "stable" }, // local build
+    "stable": {
+      "root_path": "/opt/stable"
+    }
+  }
+}"#
+        );
+    }
+
+    #[test]
+    fn test_path_at() {
+        let offset = CONFIG.find("/opt/dev").unwrap();
+        assert_eq!(path_at(CONFIG, offset), ["toolchain", "dev", "root_path"]);
+        assert_eq!(offset_of(CONFIG, 5, 21), offset);
+        assert!(path_at(CONFIG, 0).is_empty());
+    }
+}
@@ -0,0 +1,377 @@
+//! Merging the config from the system, user, project and environment layers.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde_json_lenient::Value;
+
+use super::Config;
+
+pub const SYSTEM_CONFIG_ENV_NAME: &str = "LUNIK_SYSTEM_CONFIG";
+pub const DEFAULT_TOOLCHAIN_ENV_NAME: &str = "LUNIK_DEFAULT_TOOLCHAIN";
+pub const CONFIG_OVERRIDE_ENV_NAME: &str = "LUNIK_CONFIG_OVERRIDE";
+
+/// The project config file, searched from the current directory upwards.
+pub const PROJECT_CONFIG_NAME: &str = ".lunik.json";
+
+/// The kind of a config layer, in increasing precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LayerKind {
+    System,
+    User,
+    Project,
+    Env,
+}
+
+impl std::fmt::Display for LayerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LayerKind::System => write!(f, "system"),
+            LayerKind::User => write!(f, "user"),
+            LayerKind::Project => write!(f, "project"),
+            LayerKind::Env => write!(f, "env"),
+        }
+    }
+}
+
+/// A single source of config values.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub kind: LayerKind,
+    /// The file or environment variable the layer comes from
+    pub source: String,
+    pub value: Value,
+}
+
+impl std::fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.kind, self.source)
+    }
+}
+
+/// The system config path, shared by all users.
+pub fn system_config_path() -> PathBuf {
+    match std::env::var_os(SYSTEM_CONFIG_ENV_NAME) {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ if cfg!(windows) => PathBuf::from(
+            std::env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into()),
+        )
+        .join("lunik")
+        .join(super::CONFIG_NAME),
+        _ => PathBuf::from("/etc/lunik").join(super::CONFIG_NAME),
+    }
+}
+
+/// Search for the project config file from `dir` upwards.
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Read a config file without writing to it, migrating it in memory.
+fn read_layer_file(path: &Path) -> anyhow::Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let (value, _) = super::parse_config_file(path, &text)?;
+    Ok(value)
+}
+
+/// Collect the config layers in increasing precedence. If `user` is given, it
+/// is used in place of the user config file.
+pub fn config_layers(user: Option<Value>) -> anyhow::Result<Vec<ConfigLayer>> {
+    let mut layers = vec![];
+
+    let system_path = system_config_path();
+    if system_path.is_file() {
+        layers.push(ConfigLayer {
+            kind: LayerKind::System,
+            source: system_path.display().to_string(),
+            value: read_layer_file(&system_path)?,
+        });
+    }
+
+    let user_path = super::config_path();
+    let user = match user {
+        Some(value) => Some(value),
+        // Only commands modifying the user config migrate the file, not every tool run
+        None if user_path.exists() => Some(read_layer_file(&user_path)?),
+        // The user config is optional if the system provides one
+        None if !layers.is_empty() => None,
+        None => return Err(super::config_not_found(&user_path)),
+    };
+    if let Some(value) = user {
+        layers.push(ConfigLayer {
+            kind: LayerKind::User,
+            source: user_path.display().to_string(),
+            value,
+        });
+    }
+
+    if let Some(path) = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| find_project_config(&cwd))
+    {
+        let mut value = read_layer_file(&path)?;
+        let ignored = restrict_project_layer(&mut value);
+        if !ignored.is_empty() {
+            tracing::warn!(
+                "Ignoring {} in {}: a project config may only choose among the configured toolchains",
+                ignored.join(", "),
+                path.display()
+            );
+        }
+        layers.push(ConfigLayer {
+            kind: LayerKind::Project,
+            source: path.display().to_string(),
+            value,
+        });
+    }
+
+    if let Ok(overrides) = std::env::var(CONFIG_OVERRIDE_ENV_NAME) {
+        let value = serde_json_lenient::from_str(&overrides)
+            .with_context(|| format!("Failed to parse {}", CONFIG_OVERRIDE_ENV_NAME))?;
+        layers.push(ConfigLayer {
+            kind: LayerKind::Env,
+            source: CONFIG_OVERRIDE_ENV_NAME.into(),
+            value,
+        });
+    }
+    if let Ok(default) = std::env::var(DEFAULT_TOOLCHAIN_ENV_NAME) {
+        layers.push(ConfigLayer {
+            kind: LayerKind::Env,
+            source: DEFAULT_TOOLCHAIN_ENV_NAME.into(),
+            value: serde_json_lenient::json!({ "default": default }),
+        });
+    }
+
+    // Relative paths in config files are relative to the file, and those in the
+    // environment to the current directory. The paths of the user config are
+    // expanded where they are used, as it is also read on its own for editing.
+    for layer in &mut layers {
+        let base_dir = match layer.kind {
+            LayerKind::User => continue,
+            LayerKind::Env => std::env::current_dir().ok(),
+            _ => Path::new(&layer.source).parent().map(Path::to_path_buf),
+        };
+        expand_layer_paths(&mut layer.value, base_dir.as_deref());
+    }
+
+    Ok(layers)
+}
+
+/// Remove the keys of a project config which could make lunik run other
+/// executables, so that entering an untrusted repository is safe. Only
+/// choosing among the configured toolchains is allowed. Returns the removed
+/// keys.
+fn restrict_project_layer(value: &mut Value) -> Vec<String> {
+    const ALLOWED: &[&str] = &["$schema", "version", "default", "aliases", "toolchain"];
+    const ALLOWED_TOOLCHAIN: &[&str] = &["fallback"];
+
+    let mut ignored = vec![];
+    let Some(map) = value.as_object_mut() else {
+        return ignored;
+    };
+    map.retain(|key, _| {
+        let allowed = ALLOWED.contains(&key.as_str());
+        if !allowed {
+            ignored.push(key.clone());
+        }
+        allowed
+    });
+    if let Some(toolchains) = map.get_mut("toolchain").and_then(Value::as_object_mut) {
+        toolchains.retain(|name, info| {
+            let Some(info) = info.as_object_mut() else {
+                return true;
+            };
+            let len = info.len();
+            info.retain(|key, _| {
+                let allowed = ALLOWED_TOOLCHAIN.contains(&key.as_str());
+                if !allowed {
+                    ignored.push(format!("toolchain.{}.{}", name, key));
+                }
+                allowed
+            });
+            // Don't leave behind toolchains defined only by ignored keys
+            len == info.len() || !info.is_empty()
+        });
+    }
+    ignored
+}
+
+/// Expand the paths in a config layer with [`super::expand_path`].
+fn expand_layer_paths(value: &mut Value, base_dir: Option<&Path>) {
+    let expand = |value: &mut Value| {
+        if let Value::String(s) = value {
+            *s = super::expand_path(s, base_dir).display().to_string();
+        }
+    };
+
+    if let Some(toolchains) = value.get_mut("toolchain").and_then(Value::as_object_mut) {
+        for info in toolchains.values_mut() {
+            for key in ["root_path", "core_path"] {
+                if let Some(path) = info.get_mut(key) {
+                    expand(path);
+                }
+            }
+            if let Some(overrides) = info.get_mut("override").and_then(Value::as_object_mut) {
+                overrides.values_mut().for_each(expand);
+            }
+        }
+    }
+    if let Some(bin_dir) = value.get_mut("bin_dir") {
+        expand(bin_dir);
+    }
+    if let Some(stores) = value
+        .get_mut("toolchain_stores")
+        .and_then(Value::as_array_mut)
+    {
+        stores.iter_mut().for_each(expand);
+    }
+}
+
+/// Whether the value does not set anything, and should not override lower layers.
+fn is_unset(path: &[String], value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.is_empty() && path == ["default"],
+        _ => false,
+    }
+}
+
+fn merge_value(
+    base: &mut Value,
+    overlay: &Value,
+    path: &mut Vec<String>,
+    origin: &str,
+    origins: &mut BTreeMap<Vec<String>, String>,
+) {
+    if is_unset(path, overlay) {
+        return;
+    }
+    match overlay.as_object() {
+        Some(overlay) if !overlay.is_empty() => {
+            if !base.is_object() {
+                origins.retain(|p, _| !p.starts_with(path));
+                *base = Value::Object(Default::default());
+            }
+            let base = base.as_object_mut().unwrap();
+            for (key, value) in overlay {
+                path.push(key.clone());
+                if !is_unset(path, value) {
+                    let base = base.entry(key.clone()).or_insert(Value::Null);
+                    merge_value(base, value, path, origin, origins);
+                }
+                path.pop();
+            }
+        }
+        Some(_) if base.is_object() => {}
+        _ => {
+            origins.retain(|p, _| !p.starts_with(path));
+            origins.insert(path.clone(), origin.to_string());
+            *base = overlay.clone();
+        }
+    }
+}
+
+/// Merge the layers, returning the merged value and which layer each leaf
+/// value came from.
+pub fn merge_layers(layers: &[ConfigLayer]) -> (Value, BTreeMap<Vec<String>, String>) {
+    let mut merged = Value::Object(Default::default());
+    let mut origins = BTreeMap::new();
+    for layer in layers {
+        merge_value(
+            &mut merged,
+            &layer.value,
+            &mut vec![],
+            &layer.to_string(),
+            &mut origins,
+        );
+    }
+    (merged, origins)
+}
+
+/// Merge the layers into a config.
+pub fn merged_config(layers: &[ConfigLayer]) -> anyhow::Result<Config> {
+    let (merged, _) = merge_layers(layers);
+    serde_json_lenient::from_value(merged).context("Invalid config after merging all config layers")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json_lenient::json;
+
+    fn layer(kind: LayerKind, value: Value) -> ConfigLayer {
+        ConfigLayer {
+            kind,
+            source: "test".into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_restrict_project_layer() {
+        let mut value = json!({
+            "default": "dev",
+            "bin_dir": "./bin",
+            "toolchain": {
+                "dev": { "fallback": "stable", "root_path": "./toolchain" },
+                "evil": { "override": { "moon": "./moon" }, "env": { "LD_PRELOAD": "./x.so" } },
+            },
+        });
+        let ignored = restrict_project_layer(&mut value);
+        assert_eq!(
+            ignored,
+            [
+                "bin_dir",
+                "toolchain.dev.root_path",
+                "toolchain.evil.env",
+                "toolchain.evil.override"
+            ]
+        );
+        assert_eq!(
+            value,
+            json!({
+                "default": "dev",
+                "toolchain": { "dev": { "fallback": "stable" } },
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_layers() {
+        let layers = [
+            layer(
+                LayerKind::System,
+                json!({ "toolchain": { "stable": { "root_path": "/opt/stable" } }, "default": "stable" }),
+            ),
+            layer(
+                LayerKind::User,
+                json!({ "toolchain": { "dev": { "fallback": "stable", "core_path": null } }, "default": "" }),
+            ),
+            layer(LayerKind::Env, json!({ "default": "dev" })),
+        ];
+        let (merged, origins) = merge_layers(&layers);
+        assert_eq!(
+            merged,
+            json!({
+                "toolchain": {
+                    "stable": { "root_path": "/opt/stable" },
+                    "dev": { "fallback": "stable" },
+                },
+                "default": "dev",
+            })
+        );
+
+        let origin = |path: &[&str]| {
+            origins[&path.iter().map(|s| s.to_string()).collect::<Vec<_>>()].clone()
+        };
+        assert_eq!(origin(&["toolchain", "stable", "root_path"]), "system:test");
+        assert_eq!(origin(&["toolchain", "dev", "fallback"]), "user:test");
+        assert_eq!(origin(&["default"]), "env:test");
+    }
+}
@@ -0,0 +1,155 @@
+//! The JSON Schema of the config, for editors to validate `lunik.json`.
+
+use serde_json_lenient::{json, Value};
+
+use super::CONFIG_VERSION;
+
+fn path_schema(description: &str) -> Value {
+    json!({ "type": ["string", "null"], "description": description })
+}
+
+fn toolchain_info_schema() -> Value {
+    json!({
+        "type": "object",
+        "description": "A toolchain",
+        "properties": {
+            "fallback": {
+                "type": ["string", "null"],
+                "description": "The fallback toolchain to use if this toolchain does not contain the required tool",
+            },
+            "root_path": path_schema("The root path of the toolchain"),
+            "override": {
+                "type": "object",
+                "description": "Paths of tools overriding those in the toolchain",
+                "additionalProperties": { "type": "string" },
+            },
+            "core_path": path_schema("Override path for the core library"),
+            "env": {
+                "type": "object",
+                "description": "Extra environment variables for running the tools. `$VAR` and `${VAR}` are expanded from the current environment",
+                "additionalProperties": { "type": "string" },
+            },
+            "args_prefix": {
+                "type": "object",
+                "description": "Arguments added before the user's arguments, by tool name",
+                "additionalProperties": { "type": "array", "items": { "type": "string" } },
+            },
+            "aliases": {
+                "type": "object",
+                "description": "Tool aliases. The first element is the tool to run, and the rest are arguments added before the user's arguments",
+                "additionalProperties": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "minItems": 1,
+                },
+            },
+        },
+    })
+}
+
+fn channel_info_schema() -> Value {
+    json!({
+        "type": "object",
+        "description": "A toolchain installed from a release channel",
+        "properties": {
+            "url": {
+                "type": ["string", "null"],
+                "description": "Override URL",
+            },
+        },
+    })
+}
+
+/// The JSON Schema of [`super::Config`].
+pub fn config_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "lunik config",
+        "type": "object",
+        "properties": {
+            "$schema": { "type": "string" },
+            "version": {
+                "type": "integer",
+                "description": "Version of the config layout",
+                "minimum": 0,
+                "maximum": CONFIG_VERSION,
+            },
+            "toolchain": {
+                "type": "object",
+                "description": "Toolchains by name",
+                "additionalProperties": { "$ref": "#/definitions/ToolchainInfo" },
+            },
+            "channels": {
+                "type": "object",
+                "description": "Toolchains installed from release channels, by name",
+                "additionalProperties": { "$ref": "#/definitions/ChannelInfo" },
+            },
+            "default": {
+                "type": "string",
+                "description": "The default toolchain",
+            },
+            "aliases": {
+                "type": "object",
+                "description": "Toolchain aliases, like `stable` pointing at a channel toolchain",
+                "additionalProperties": { "type": "string" },
+            },
+            "bin_dir": path_schema("Directory of the lunik executable and the tool shims, if not `~/.moon/bin`"),
+            "toolchain_stores": {
+                "type": "array",
+                "description": "Read-only directories containing toolchains",
+                "items": { "type": "string" },
+            },
+        },
+        "definitions": {
+            "ToolchainInfo": toolchain_info_schema(),
+            "ChannelInfo": channel_info_schema(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ChannelInfo, Config, ToolchainInfo};
+
+    fn assert_covers(schema: &Value, value: &Value) {
+        for key in value.as_object().unwrap().keys() {
+            assert!(
+                schema["properties"].get(key).is_some(),
+                "`{}` is missing in the schema",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_schema_covers_config() {
+        let mut info = ToolchainInfo::default();
+        info.override_.insert("moon".into(), "/bin/moon".into());
+        info.env.insert("MOON_DEBUG".into(), "1".into());
+        info.args_prefix
+            .insert("moon".into(), vec!["--quiet".into()]);
+        info.aliases
+            .insert("moonfmt".into(), vec!["moon".into(), "fmt".into()]);
+        let mut config = Config {
+            toolchain_stores: vec!["/opt".into()],
+            bin_dir: Some("/usr/local/bin".into()),
+            ..Default::default()
+        };
+        config.toolchain.insert("dev".into(), info);
+        config.aliases.insert("stable".into(), "dev".into());
+        config.channels.insert("dev".into(), ChannelInfo::default());
+        let value = serde_json_lenient::to_value(&config).unwrap();
+
+        let schema = config_schema();
+        assert_covers(&schema, &value);
+        assert_covers(
+            &schema["definitions"]["ToolchainInfo"],
+            &value["toolchain"]["dev"],
+        );
+        assert_covers(
+            &schema["definitions"]["ChannelInfo"],
+            &value["channels"]["dev"],
+        );
+    }
+}
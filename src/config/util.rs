@@ -27,6 +27,12 @@ impl<'a> Iterator for ConfigToolchainFallbackIter<'a> {
         // Take the current toolchain name to process. If none, iterator is finished.
         let toolchain_name = self.curr_toolchain_name.take()?;
 
+        // Resolve aliases. Their targets are never aliases themselves.
+        let toolchain_name = match self.config.aliases.get(toolchain_name.as_ref()) {
+            Some(target) => Cow::Borrowed(target.as_str()),
+            None => toolchain_name,
+        };
+
         // Attempt to find the toolchain information
         let (real_toolchain_name, toolchain_info) =
             if let Some(info) = self.config.toolchain.get(toolchain_name.as_ref()) {
@@ -1,18 +1,81 @@
-use std::{borrow::Cow, path::PathBuf};
+use std::{borrow::Cow, collections::BTreeMap, ffi::OsString, path::PathBuf};
 
-use crate::config::{Config, ToolchainInfo, LUNIK_HOME_ENV_NAME, MOON_HOME_ENV_NAME};
+use crate::config::{Config, LayerKind, ToolchainInfo, LUNIK_HOME_ENV_NAME, MOON_HOME_ENV_NAME};
 pub const LUNIK_TOOLCHAIN_ENV_NAME: &str = "LUNIK_TOOLCHAIN";
+/// Set to the value of `MOON_CORE_OVERRIDE` when lunik computed it, so that it
+/// is recomputed instead of being taken as the user's choice.
+pub const LUNIK_CORE_OVERRIDE_ENV_NAME: &str = "LUNIK_CORE_OVERRIDE";
+
+/// Where the choice of toolchain came from.
+#[derive(Debug, Clone)]
+pub enum ToolchainSource {
+    /// A `+toolchain` argument
+    Arg,
+    /// The `LUNIK_TOOLCHAIN` environment variable
+    Env,
+    /// The `default` of a project config file
+    ProjectFile(PathBuf),
+    /// The `default` set by an environment variable overriding the config
+    Override(String),
+    /// The default toolchain in the config
+    Default,
+}
+
+impl std::fmt::Display for ToolchainSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ToolchainSource::Arg => write!(f, "+toolchain argument"),
+            ToolchainSource::Env => write!(f, "{} environment variable", LUNIK_TOOLCHAIN_ENV_NAME),
+            ToolchainSource::ProjectFile(path) => write!(f, "project config {}", path.display()),
+            ToolchainSource::Override(var) => write!(f, "{} environment variable", var),
+            ToolchainSource::Default => write!(f, "default toolchain"),
+        }
+    }
+}
+
+/// Decide which toolchain to use.
+///
+/// The precedence is: `+toolchain` argument, `LUNIK_TOOLCHAIN`, and finally the
+/// default toolchain of the merged config. `None` is returned for the default
+/// toolchain; use [`default_toolchain_source`] to find where it was set.
+pub fn resolve_toolchain(arg: Option<&str>) -> (Option<String>, ToolchainSource) {
+    if let Some(toolchain) = arg {
+        return (Some(toolchain.to_string()), ToolchainSource::Arg);
+    }
+    if let Ok(toolchain) = std::env::var(LUNIK_TOOLCHAIN_ENV_NAME) {
+        return (Some(toolchain), ToolchainSource::Env);
+    }
+    (None, ToolchainSource::Default)
+}
+
+/// Find the config layer which sets the default toolchain.
+pub fn default_toolchain_source() -> anyhow::Result<ToolchainSource> {
+    let layers = crate::config::config_layers(None)?;
+    let layer = layers.iter().rev().find(|layer| {
+        layer
+            .value
+            .get("default")
+            .and_then(|default| default.as_str())
+            .is_some_and(|default| !default.is_empty())
+    });
+    Ok(match layer {
+        Some(layer) if layer.kind == LayerKind::Project => {
+            ToolchainSource::ProjectFile(PathBuf::from(&layer.source))
+        }
+        Some(layer) if layer.kind == LayerKind::Env => {
+            ToolchainSource::Override(layer.source.clone())
+        }
+        _ => ToolchainSource::Default,
+    })
+}
 
 pub fn entry(binary_name: &str, argv: &[String]) -> anyhow::Result<()> {
     // Check if the next argument starts with "+"
     // If it does, it specifies which version of the toolchain to use
-    // Otherwise, we check if we have specified the toolchain in the environment variable
-    let mux_toolchain = argv
-        .first()
-        .and_then(|arg| arg.strip_prefix('+'))
-        .map(|toolchain| toolchain.to_string());
+    // Otherwise, we check the environment variable and the project toolchain file
+    let mux_toolchain = argv.first().and_then(|arg| arg.strip_prefix('+'));
     let toolchain_arg_present = mux_toolchain.is_some();
-    let mux_toolchain = mux_toolchain.or_else(|| std::env::var(LUNIK_TOOLCHAIN_ENV_NAME).ok());
+    let (mux_toolchain, _) = resolve_toolchain(mux_toolchain);
 
     let argv = if toolchain_arg_present {
         &argv[1..]
@@ -22,7 +85,7 @@ pub fn entry(binary_name: &str, argv: &[String]) -> anyhow::Result<()> {
 
     let cfg = crate::config::read_config()?;
 
-    let mut cmd = executable_entry(&cfg, mux_toolchain.as_deref(), binary_name)?;
+    let mut cmd = tool_command(&cfg, mux_toolchain.as_deref(), binary_name)?;
     let cmd = cmd.args(argv);
 
     let status = cmd.status()?;
@@ -37,14 +100,36 @@ pub fn real_toolchain_name<'a>(
     cfg: &Config,
     toolchain_name: &'a str,
 ) -> anyhow::Result<Cow<'a, str>> {
-    if cfg.toolchain.contains_key(toolchain_name) {
-        Ok(Cow::Borrowed(toolchain_name))
+    let toolchain_name = match cfg.aliases.get(toolchain_name) {
+        Some(target) => Cow::Owned(target.clone()),
+        None => Cow::Borrowed(toolchain_name),
+    };
+    if cfg.toolchain.contains_key(toolchain_name.as_ref()) {
+        Ok(toolchain_name)
     } else {
         let ch = toolchain_name.parse::<super::channel::Channel>()?;
         Ok(Cow::Owned(ch.to_string()))
     }
 }
 
+/// Prepare the command to run a tool for the user, resolving tool aliases and
+/// adding the `args_prefix` of the tool.
+pub fn tool_command(
+    cfg: &Config,
+    toolchain_name: Option<&str>,
+    tool: &str,
+) -> anyhow::Result<std::process::Command> {
+    let (executable_name, alias_args) = resolve_tool(cfg, toolchain_name, tool);
+    let mut cmd = executable_entry(cfg, toolchain_name, executable_name)?;
+    cmd.args(tool_args_prefix(cfg, toolchain_name, executable_name))
+        .args(alias_args);
+    Ok(cmd)
+}
+
+/// Prepare the command to run an executable of the toolchain, in its environment.
+///
+/// The `args_prefix` of the tool is not added, as it is only meant for the
+/// user's invocations, not for lunik running the tools itself.
 pub fn executable_entry(
     cfg: &Config,
     toolchain_name: Option<&str>,
@@ -63,19 +148,104 @@ pub fn configure_cmd_environment(
     toolchain_name: Option<&str>,
     cfg: &Config,
 ) -> Result<(), anyhow::Error> {
-    cmd.env(LUNIK_HOME_ENV_NAME, crate::config::home_dir());
+    // The user's override only applies if the toolchain is not chosen explicitly
+    let inherit_core_override = toolchain_name.is_none() && user_core_override().is_some();
+    cmd.envs(toolchain_environment(
+        cfg,
+        toolchain_name,
+        inherit_core_override,
+    )?);
+    cmd.envs(toolchain_extra_env(cfg, toolchain_name));
+    Ok(())
+}
+
+/// Collect the `env` of the toolchain and its fallbacks, with nearer
+/// toolchains taking precedence.
+pub fn toolchain_extra_env(cfg: &Config, toolchain_name: Option<&str>) -> BTreeMap<String, String> {
+    let mut envs = BTreeMap::new();
+    for (_, info) in cfg.toolchain_fallback_iter(toolchain_name.unwrap_or(&cfg.default)) {
+        for (name, value) in &info.env {
+            envs.entry(name.clone()).or_insert_with(|| {
+                crate::config::expand_vars(value, |var| std::env::var(var).ok())
+            });
+        }
+    }
+    envs
+}
+
+/// Find the `args_prefix` of a tool in the toolchain or its nearest fallback.
+pub fn tool_args_prefix<'a>(
+    cfg: &'a Config,
+    toolchain_name: Option<&'a str>,
+    executable_name: &str,
+) -> &'a [String] {
+    let executable_name = executable_name.trim_end_matches(".exe");
+    cfg.toolchain_fallback_iter(toolchain_name.unwrap_or(&cfg.default))
+        .find_map(|(_, info)| info.args_prefix.get(executable_name))
+        .map_or(&[], |args| args.as_slice())
+}
+
+/// Find the alias of a tool in the toolchain or its nearest fallback. The
+/// target of an alias is not resolved as an alias again.
+pub fn tool_alias<'a>(
+    cfg: &'a Config,
+    toolchain_name: Option<&'a str>,
+    executable_name: &str,
+) -> Option<&'a [String]> {
+    let executable_name = executable_name.trim_end_matches(".exe");
+    cfg.toolchain_fallback_iter(toolchain_name.unwrap_or(&cfg.default))
+        .find_map(|(_, info)| info.aliases.get(executable_name))
+        .map(|target| target.as_slice())
+}
+
+/// Resolve a tool alias to the executable to run and the arguments the alias
+/// adds. Other tools are returned as is.
+pub fn resolve_tool<'a>(
+    cfg: &'a Config,
+    toolchain_name: Option<&'a str>,
+    tool: &'a str,
+) -> (&'a str, &'a [String]) {
+    match tool_alias(cfg, toolchain_name, tool) {
+        Some([executable_name, args @ ..]) => (executable_name.as_str(), args),
+        _ => (tool, &[]),
+    }
+}
+
+/// The `MOON_CORE_OVERRIDE` set by the user in the current environment, if any.
+/// Values exported by lunik itself, like by `lunik env` or the shell hook, are
+/// not the user's.
+pub fn user_core_override() -> Option<OsString> {
+    let value = std::env::var_os(crate::config::MOON_CORE_OVERRIDE_ENV_NAME)?;
+    let from_lunik = std::env::var_os(LUNIK_CORE_OVERRIDE_ENV_NAME).is_some_and(|v| v == value);
+    (!from_lunik).then_some(value)
+}
+
+/// Compute the environment variables for running tools in the given toolchain.
+///
+/// If `inherit_core_override` is set, `MOON_CORE_OVERRIDE` is left out so that
+/// the value in the current environment is used.
+pub fn toolchain_environment(
+    cfg: &Config,
+    toolchain_name: Option<&str>,
+    inherit_core_override: bool,
+) -> anyhow::Result<Vec<(&'static str, OsString)>> {
+    let mut envs = vec![(LUNIK_HOME_ENV_NAME, crate::config::home_dir().into())];
     if let Some(toolchain) = toolchain_name {
-        cmd.env(LUNIK_TOOLCHAIN_ENV_NAME, toolchain);
+        envs.push((LUNIK_TOOLCHAIN_ENV_NAME, toolchain.into()));
     }
-    cmd.env(
+    envs.push((
         MOON_HOME_ENV_NAME,
-        try_get_toolchain_home(cfg, toolchain_name)?,
-    );
-    if std::env::var(crate::config::MOON_CORE_OVERRIDE_ENV_NAME).is_err() {
+        try_get_toolchain_home(cfg, toolchain_name)?.into(),
+    ));
+    if !inherit_core_override {
         let core_lib_path = try_get_core_lib(cfg, toolchain_name)?;
-        cmd.env(crate::config::MOON_CORE_OVERRIDE_ENV_NAME, core_lib_path);
-    };
-    Ok(())
+        envs.push((
+            crate::config::MOON_CORE_OVERRIDE_ENV_NAME,
+            core_lib_path.clone().into(),
+        ));
+        envs.push((LUNIK_CORE_OVERRIDE_ENV_NAME, core_lib_path.into()));
+    }
+    Ok(envs)
 }
 
 pub fn try_get_toolchain_home(
@@ -86,10 +256,7 @@ pub fn try_get_toolchain_home(
 
     for (name, info) in cfg.toolchain_fallback_iter(initial_toolchain_name) {
         if info.fallback.is_none() {
-            return Ok(info
-                .root_path
-                .clone()
-                .unwrap_or_else(|| crate::config::toolchain_path(&name)));
+            return Ok(cfg.toolchain_dir(&name));
         }
     }
 
@@ -99,11 +266,25 @@ pub fn try_get_toolchain_home(
     ))
 }
 
-pub fn try_get_executable(
+/// A candidate location of a tool or the core library in one toolchain of a fallback chain.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Candidate {
+    /// The toolchain providing this candidate
+    pub toolchain: String,
+    /// The candidate path
+    pub path: PathBuf,
+    /// Whether the path comes from an override in the config
+    pub from_override: bool,
+    /// Whether the path exists
+    pub exists: bool,
+}
+
+/// List the candidate paths of an executable along the fallback chain of a toolchain.
+pub fn executable_candidates(
     cfg: &Config,
     toolchain: Option<&str>,
     executable_name: &str,
-) -> anyhow::Result<PathBuf> {
+) -> Vec<Candidate> {
     let initial_toolchain_name = toolchain.unwrap_or(&cfg.default);
 
     // Strip .exe suffix from executable_name if on Windows
@@ -113,40 +294,50 @@ pub fn try_get_executable(
         executable_name
     };
 
-    for (name, info) in cfg.toolchain_fallback_iter(initial_toolchain_name) {
-        let executable_path = get_toolchain_executable(&name, info, executable_name_base);
-        if executable_path.exists() {
-            return Ok(executable_path);
-        } else {
-            // Optional: Add logging if needed
-            // eprintln!(
-            //     "Executable '{}' not found in toolchain '{}', trying next in fallback chain",
-            //     executable_name, &name
-            // );
-        }
-    }
+    cfg.toolchain_fallback_iter(initial_toolchain_name)
+        .map(|(name, info)| {
+            let path = get_toolchain_executable(cfg, &name, info, executable_name_base);
+            Candidate {
+                toolchain: name.into_owned(),
+                exists: path.exists(),
+                from_override: info.override_.contains_key(executable_name_base),
+                path,
+            }
+        })
+        .collect()
+}
 
-    Err(anyhow::anyhow!(
-        "Executable '{}' not found in toolchain '{}' or any of its fallbacks",
-        executable_name,
-        initial_toolchain_name
-    ))
+pub fn try_get_executable(
+    cfg: &Config,
+    toolchain: Option<&str>,
+    executable_name: &str,
+) -> anyhow::Result<PathBuf> {
+    let initial_toolchain_name = toolchain.unwrap_or(&cfg.default);
+
+    executable_candidates(cfg, toolchain, executable_name)
+        .into_iter()
+        .find(|candidate| candidate.exists)
+        .map(|candidate| candidate.path)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Executable '{}' not found in toolchain '{}' or any of its fallbacks",
+                executable_name,
+                initial_toolchain_name
+            )
+        })
 }
 
 fn get_toolchain_executable(
+    cfg: &Config,
     toolchain_name: &str,
     toolchain: &ToolchainInfo,
     executable_name: &str,
 ) -> PathBuf {
     if let Some(path) = toolchain.override_.get(executable_name) {
-        return path.clone();
+        return crate::config::expand_config_path(path);
     }
 
-    let toolchain_root = toolchain
-        .root_path
-        .clone()
-        .unwrap_or_else(|| crate::config::toolchain_path(toolchain_name))
-        .join("bin");
+    let toolchain_root = cfg.toolchain_dir(toolchain_name).join("bin");
     let executable_name = if cfg!(windows) {
         format!("{}.exe", executable_name)
     } else {
@@ -155,17 +346,33 @@ fn get_toolchain_executable(
     toolchain_root.join(executable_name)
 }
 
-fn try_get_core_lib(cfg: &Config, toolchain: Option<&str>) -> anyhow::Result<PathBuf> {
+/// List the candidate paths of the core library along the fallback chain of a toolchain.
+pub fn core_lib_candidates(cfg: &Config, toolchain: Option<&str>) -> Vec<Candidate> {
     let initial_toolchain_name = toolchain.unwrap_or(&cfg.default);
 
-    for (name, info) in cfg.toolchain_fallback_iter(initial_toolchain_name) {
-        let core_lib_path = get_toolchain_core_lib(&name, info);
-        if core_lib_path.exists() {
-            return Ok(core_lib_path);
+    cfg.toolchain_fallback_iter(initial_toolchain_name)
+        .map(|(name, info)| {
+            let path = get_toolchain_core_lib(cfg, &name, info);
+            Candidate {
+                toolchain: name.into_owned(),
+                exists: path.exists(),
+                from_override: info.core_path.is_some(),
+                path,
+            }
+        })
+        .collect()
+}
+
+pub fn try_get_core_lib(cfg: &Config, toolchain: Option<&str>) -> anyhow::Result<PathBuf> {
+    let initial_toolchain_name = toolchain.unwrap_or(&cfg.default);
+
+    for candidate in core_lib_candidates(cfg, toolchain) {
+        if candidate.exists {
+            return Ok(candidate.path);
         } else {
             eprintln!(
                 "Core library not found in toolchain '{}', trying next in fallback chain",
-                &name
+                &candidate.toolchain
             );
         }
     }
@@ -176,14 +383,67 @@ fn try_get_core_lib(cfg: &Config, toolchain: Option<&str>) -> anyhow::Result<Pat
     ))
 }
 
-fn get_toolchain_core_lib(toolchain_name: &str, toolchain: &ToolchainInfo) -> PathBuf {
+fn get_toolchain_core_lib(
+    cfg: &Config,
+    toolchain_name: &str,
+    toolchain: &ToolchainInfo,
+) -> PathBuf {
     if let Some(path) = &toolchain.core_path {
-        return path.clone();
+        return crate::config::expand_config_path(path);
+    }
+
+    cfg.toolchain_dir(toolchain_name).join("lib/core")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn alias_config() -> Config {
+        let mut stable = ToolchainInfo::default();
+        stable
+            .aliases
+            .insert("moonfmt".into(), vec!["moon".into(), "fmt".into()]);
+        stable
+            .aliases
+            .insert("mooncheck".into(), vec!["moon".into()]);
+        stable
+            .args_prefix
+            .insert("moon".into(), vec!["--quiet".into()]);
+        let dev = ToolchainInfo {
+            fallback: Some("stable".into()),
+            aliases: [("mooncheck".to_string(), vec!["moon".into(), "check".into()])].into(),
+            ..Default::default()
+        };
+        Config {
+            toolchain: [("stable".to_string(), stable), ("dev".to_string(), dev)].into(),
+            default: "dev".into(),
+            ..Default::default()
+        }
     }
 
-    toolchain
-        .root_path
-        .clone()
-        .unwrap_or_else(|| crate::config::toolchain_path(toolchain_name))
-        .join("lib/core")
+    #[test]
+    fn test_tool_alias() {
+        let cfg = alias_config();
+        // Found in the fallback, and the nearer toolchain takes precedence
+        assert_eq!(
+            tool_alias(&cfg, Some("dev"), "moonfmt"),
+            Some(&["moon".to_string(), "fmt".to_string()][..])
+        );
+        assert_eq!(
+            resolve_tool(&cfg, None, "mooncheck"),
+            ("moon", &["check".to_string()][..])
+        );
+        assert_eq!(
+            resolve_tool(&cfg, Some("stable"), "mooncheck"),
+            ("moon", &[][..])
+        );
+        assert_eq!(
+            resolve_tool(&cfg, Some("dev"), "moonfmt.exe"),
+            ("moon", &["fmt".to_string()][..])
+        );
+        assert_eq!(resolve_tool(&cfg, Some("dev"), "moonc"), ("moonc", &[][..]));
+        assert_eq!(tool_args_prefix(&cfg, None, "moon.exe"), ["--quiet"]);
+        assert!(tool_args_prefix(&cfg, None, "moonc").is_empty());
+    }
 }
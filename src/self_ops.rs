@@ -1,5 +1,13 @@
+mod alias;
 mod channel;
+mod completions;
+mod config;
+mod env;
+mod gc;
 mod init;
+mod matrix;
+mod show;
+mod uninstall;
 
 use std::{
     io::Write,
@@ -9,17 +17,95 @@ use std::{
 use anyhow::Context;
 use clap::Parser;
 
-use crate::mux::LUNIK_TOOLCHAIN_ENV_NAME;
-
 /// The MoonBit toolchain multiplexer.
 ///
 /// Symlink this binary with other names to call the corresponding tools.
 #[derive(clap::Parser, Debug)]
 struct Cli {
+    /// The output format of informational commands and errors.
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     #[clap(subcommand)]
     cmd: Cmd,
 }
 
+/// Whether the arguments ask for JSON output, for errors before they are parsed.
+fn args_want_json(args: impl IntoIterator<Item = String>) -> bool {
+    let mut args = args.into_iter().take_while(|arg| arg != "--");
+    while let Some(arg) = args.next() {
+        let format = match arg.strip_prefix("--format") {
+            Some("") => args.next(),
+            Some(value) => value.strip_prefix('=').map(String::from),
+            None => continue,
+        };
+        if format.as_deref() == Some("json") {
+            return true;
+        }
+    }
+    false
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// Machine-readable JSON, printed to stdout. Logs are printed to stderr.
+    Json,
+}
+
+impl Cli {
+    fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+}
+
+/// Print a value as JSON to stdout.
+fn print_json(value: &impl serde::Serialize) -> anyhow::Result<()> {
+    println!("{}", serde_json_lenient::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// The result of an operation which changes the installation, in JSON output.
+#[derive(Debug, Default, serde::Serialize)]
+struct OperationOutput {
+    /// The operation performed, like `add` or `default`
+    operation: &'static str,
+    /// The toolchains affected
+    toolchains: Vec<String>,
+    /// The new default toolchain, if it was changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    /// Toolchains whose fallback was changed, with their new fallback
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changed_fallbacks: Vec<ChangedFallback>,
+    /// Toolchain aliases removed along with their toolchain
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed_aliases: Vec<String>,
+}
+
+/// A toolchain whose fallback was changed by an operation.
+#[derive(Debug, serde::Serialize)]
+struct ChangedFallback {
+    toolchain: String,
+    /// The new fallback, or `None` if it was cleared
+    fallback: Option<String>,
+}
+
+/// Print an error as JSON to stdout.
+fn print_json_error(e: &anyhow::Error) {
+    let output = serde_json_lenient::json!({
+        "error": {
+            "message": e.to_string(),
+            "causes": e.chain().skip(1).map(|c| c.to_string()).collect::<Vec<_>>(),
+        }
+    });
+    println!(
+        "{}",
+        serde_json_lenient::to_string_pretty(&output).unwrap_or_default()
+    );
+}
+
 #[derive(clap::Parser, Debug)]
 enum Cmd {
     Link(LinkSubcommand),
@@ -33,20 +119,50 @@ enum Cmd {
 
     Default(channel::DefaultSubcommand),
 
+    /// Manage toolchain aliases, like `stable` pointing at a channel toolchain.
+    #[clap(subcommand)]
+    Alias(alias::AliasCommandline),
+
+    /// Read and edit the lunik config.
+    #[clap(subcommand)]
+    Config(config::ConfigCommandline),
+
     Which(WhichSubcommand),
 
+    Show(show::ShowSubcommand),
+
     With(WithCommand),
 
+    Run(RunCommand),
+
+    Matrix(matrix::MatrixSubcommand),
+
+    Env(env::EnvSubcommand),
+
     /// Nuke the entire MoonBit installation.
     Nuke(NukeCommand),
+
+    /// Manage the lunik installation itself.
+    #[clap(name = "self", subcommand)]
+    SelfOps(uninstall::SelfCommandline),
+
+    Gc(gc::GcSubcommand),
+
+    Completions(completions::CompletionsSubcommand),
+
+    #[clap(hide = true)]
+    CompleteToolchains(completions::CompleteToolchainsSubcommand),
 }
 
 /// Symlink the current binary to the specified path(s).
 #[derive(clap::Parser, Debug)]
-#[clap(override_usage = "lunik link <PATH> \n    lunik link <PATH> <BINARYIES>...")]
+#[clap(
+    override_usage = "lunik link <PATH> \n    lunik link <PATH> <BINARYIES>...\n    lunik link --sync"
+)]
 struct LinkSubcommand {
     /// The target symlink path
-    path: PathBuf,
+    #[clap(required_unless_present = "sync")]
+    path: Option<PathBuf>,
 
     /// The binaries to symlink. If specified, `path` must be a directory.
     binaries: Vec<String>,
@@ -54,39 +170,81 @@ struct LinkSubcommand {
     /// Delete the target files if they exist.
     #[clap(short, long)]
     force: bool,
+
+    /// Sync the shims in the bin directory with the executables of all configured toolchains,
+    /// creating missing shims and removing stale ones.
+    #[clap(long, conflicts_with_all = ["path", "binaries", "force"])]
+    sync: bool,
 }
 
 pub fn entry() -> anyhow::Result<()> {
-    tracing_subscriber::FmtSubscriber::builder()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::builder()
-                .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
-
-    let cli = Cli::parse();
-    match &cli.cmd {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        // `--help` and `--version` are not errors
+        Err(e) if e.use_stderr() && args_want_json(std::env::args().skip(1)) => {
+            print_json_error(&anyhow::anyhow!("{}", e.render().to_string().trim_end()));
+            std::process::exit(e.exit_code());
+        }
+        Err(e) => e.exit(),
+    };
+
+    let subscriber = tracing_subscriber::FmtSubscriber::builder().with_env_filter(
+        tracing_subscriber::EnvFilter::builder()
+            .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
+            .from_env_lossy(),
+    );
+    if cli.is_json() {
+        // Keep stdout clean for the JSON output
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+
+    let res = match &cli.cmd {
         Cmd::Link(link) => handle_link(&cli, link),
         Cmd::InitConfig => handle_init_config(false),
         Cmd::Init(init) => init::handle_init(init),
         Cmd::Channel(cmd) => channel::entry(&cli, cmd),
         Cmd::Default(default) => channel::handle_default(&cli, default),
+        Cmd::Alias(cmd) => alias::entry(&cli, cmd),
+        Cmd::Config(cmd) => config::entry(&cli, cmd),
         Cmd::Which(which) => handle_which(&cli, which),
+        Cmd::Show(show) => show::handle_show(&cli, show),
         Cmd::With(with) => handle_with(&cli, with),
+        Cmd::Run(run) => handle_run(&cli, run),
+        Cmd::Matrix(cmd) => matrix::handle_matrix(&cli, cmd),
+        Cmd::Env(env) => env::handle_env(&cli, env),
         Cmd::Nuke(cmd) => handle_nuke(&cli, cmd),
+        Cmd::SelfOps(cmd) => uninstall::entry(&cli, cmd),
+        Cmd::Gc(cmd) => gc::handle_gc(&cli, cmd),
+        Cmd::Completions(cmd) => completions::handle_completions(&cli, cmd),
+        Cmd::CompleteToolchains(cmd) => completions::handle_complete_toolchains(&cli, cmd),
+    };
+
+    match res {
+        Err(e) if cli.is_json() => {
+            print_json_error(&e);
+            std::process::exit(1);
+        }
+        res => res,
     }
 }
 
 fn handle_link(_cli: &Cli, cmd: &LinkSubcommand) -> anyhow::Result<()> {
+    if cmd.sync {
+        let cfg = crate::config::read_config()?;
+        return channel::sync_shims(&cfg);
+    }
+
     let self_path = std::env::current_exe().unwrap();
+    let path = cmd.path.as_ref().expect("clap ensures path is present");
 
     let symlink_targets = if cmd.binaries.is_empty() {
-        vec![cmd.path.clone()]
+        vec![path.clone()]
     } else {
         cmd.binaries
             .iter()
-            .map(|binary| cmd.path.join(binary))
+            .map(|binary| path.join(binary))
             .collect()
     };
 
@@ -160,8 +318,41 @@ pub fn symlink_to(from: &Path, to: &Path) -> anyhow::Result<()> {
 }
 
 pub fn symlink_self_to(path: &Path) -> anyhow::Result<()> {
+    symlink_to(&installed_self_path()?, path)
+}
+
+/// The lunik executable that shims should point to. This is the one installed
+/// in the bin directory if it exists, otherwise the current executable.
+pub fn installed_self_path() -> anyhow::Result<PathBuf> {
     let self_exe = std::env::current_exe()?;
-    symlink_to(&self_exe, path)
+    let installed = crate::config::moon_bin_dir().join(self_exe.file_name().unwrap());
+    if installed.is_file() && !installed.is_symlink() {
+        Ok(installed)
+    } else {
+        Ok(self_exe)
+    }
+}
+
+/// Check if the path is a symlink pointing to a lunik executable.
+pub fn is_lunik_shim(path: &Path) -> bool {
+    if !path.is_symlink() {
+        return false;
+    }
+    let Ok(target) = std::fs::read_link(path) else {
+        return false;
+    };
+    if target
+        .file_stem()
+        .is_some_and(|stem| stem == crate::BINARY_NAME)
+    {
+        return true;
+    }
+    match (path.canonicalize(), std::env::current_exe()) {
+        (Ok(resolved), Ok(self_exe)) => self_exe
+            .canonicalize()
+            .is_ok_and(|self_exe| self_exe == resolved),
+        _ => false,
+    }
 }
 
 fn handle_init_config(allow_existing: bool) -> anyhow::Result<()> {
@@ -187,31 +378,81 @@ fn handle_init_config(allow_existing: bool) -> anyhow::Result<()> {
 
 /// Get the path of the binary in the specified toolchain.
 #[derive(clap::Parser, Debug)]
-#[clap(override_usage = "lunik which <BINARY> | lunik which <TOOLCHAIN> <BINARY>")]
+#[clap(override_usage = "lunik which [--all] <BINARY> | lunik which [--all] <TOOLCHAIN> <BINARY>")]
 struct WhichSubcommand {
     #[clap(hide(true))]
     arg1: String,
 
     #[clap(hide(true))]
     arg2: Option<String>,
+
+    /// List every candidate along the fallback chain, including the core library.
+    #[clap(short, long)]
+    all: bool,
 }
 
-fn handle_which(_cli: &Cli, cmd: &WhichSubcommand) -> anyhow::Result<()> {
+fn handle_which(cli: &Cli, cmd: &WhichSubcommand) -> anyhow::Result<()> {
     let cfg = crate::config::read_config()?;
 
     let binary = cmd.arg2.clone().unwrap_or(cmd.arg1.clone());
-    let toolchain = if cmd.arg2.is_some() {
-        Some(cmd.arg1.clone())
-    } else {
-        std::env::var(LUNIK_TOOLCHAIN_ENV_NAME).ok()
-    };
+    let (toolchain, _) =
+        crate::mux::resolve_toolchain(cmd.arg2.is_some().then_some(cmd.arg1.as_str()));
+    let toolchain_name = toolchain.clone().unwrap_or(cfg.default.clone());
+    let (binary, _) = crate::mux::resolve_tool(&cfg, toolchain.as_deref(), &binary);
+    let binary = binary.to_string();
+
+    if cmd.all {
+        let candidates = crate::mux::executable_candidates(&cfg, toolchain.as_deref(), &binary);
+        let core_lib = crate::mux::core_lib_candidates(&cfg, toolchain.as_deref());
+        if cli.is_json() {
+            return print_json(&serde_json_lenient::json!({
+                "binary": binary,
+                "toolchain": toolchain_name,
+                "candidates": candidates,
+                "core_lib": core_lib,
+            }));
+        }
+        println!("{}:", binary);
+        print_candidates(&candidates);
+        println!("core library:");
+        print_candidates(&core_lib);
+        return Ok(());
+    }
 
     let executable_path = crate::mux::try_get_executable(&cfg, toolchain.as_deref(), &binary)?;
+    if cli.is_json() {
+        return print_json(&serde_json_lenient::json!({
+            "binary": binary,
+            "toolchain": toolchain_name,
+            "path": executable_path,
+        }));
+    }
     println!("{}", executable_path.display());
 
     Ok(())
 }
 
+fn print_candidates(candidates: &[crate::mux::Candidate]) {
+    if candidates.is_empty() {
+        println!("  <toolchain not found>");
+    }
+    let width = candidates
+        .iter()
+        .map(|c| c.toolchain.len())
+        .max()
+        .unwrap_or(0);
+    for c in candidates {
+        println!(
+            "  {:width$}  {}  {}{}",
+            c.toolchain,
+            if c.exists { "found  " } else { "missing" },
+            c.path.display(),
+            if c.from_override { " (override)" } else { "" },
+            width = width
+        );
+    }
+}
+
 /// Set the environment so that Lunik invocations in the given command will use the specified toolchain.
 #[derive(clap::Parser, Debug)]
 struct WithCommand {
@@ -233,11 +474,22 @@ fn handle_with(_cli: &Cli, cmd: &WithCommand) -> anyhow::Result<()> {
     let binary_name = &cmd_args[0];
     let cmd_args = &cmd_args[1..];
 
-    let mut cmd = std::process::Command::new(binary_name);
+    let config = crate::config::read_config()?;
+    // Tool aliases may have no shim on `PATH`, so run them directly
+    let mut cmd = if crate::mux::tool_alias(&config, Some(&toolchain), binary_name).is_some() {
+        crate::mux::tool_command(&config, Some(&toolchain), binary_name)?
+    } else {
+        let mut cmd = std::process::Command::new(binary_name);
+        crate::mux::configure_cmd_environment(&mut cmd, Some(&toolchain), &config)?;
+        cmd
+    };
     cmd.args(cmd_args);
+    exec_command(cmd)
+}
 
-    let config = crate::config::read_config()?;
-    crate::mux::configure_cmd_environment(&mut cmd, Some(&toolchain), &config)?;
+/// Replace the current process with the command, or run it and exit with its
+/// status where that is not possible.
+fn exec_command(mut cmd: std::process::Command) -> anyhow::Result<()> {
     #[cfg(unix)]
     {
         use std::os::unix::process::CommandExt;
@@ -249,7 +501,67 @@ fn handle_with(_cli: &Cli, cmd: &WithCommand) -> anyhow::Result<()> {
         if !status.success() {
             std::process::exit(status.code().unwrap_or(1));
         }
+        Ok(())
+    }
+}
+
+/// Resolve the name of a configured toolchain, or list the configured ones.
+fn find_toolchain(config: &crate::config::Config, name: &str) -> anyhow::Result<String> {
+    // Check first, as names which are not configured may not parse as a channel
+    if !config.contains_toolchain(name) {
+        let mut names = config
+            .toolchain
+            .keys()
+            .chain(config.aliases.keys())
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort();
+        anyhow::bail!(
+            "Toolchain not found: {}\nConfigured toolchains: {}",
+            name,
+            names.join(", ")
+        );
+    }
+    Ok(crate::mux::real_toolchain_name(config, name)?.into_owned())
+}
+
+/// Run a tool from the specified toolchain, even if it has no shim.
+#[derive(clap::Parser, Debug)]
+struct RunCommand {
+    /// The name of the toolchain to use
+    toolchain: String,
+
+    /// The tool to run
+    tool: String,
+
+    /// Arguments passed to the tool
+    #[clap(trailing_var_arg(true), allow_hyphen_values(true))]
+    args: Vec<String>,
+}
+
+fn handle_run(_cli: &Cli, cmd: &RunCommand) -> anyhow::Result<()> {
+    let config = crate::config::read_config()?;
+    let toolchain = find_toolchain(&config, &cmd.toolchain)?;
+
+    // Only a missing tool gets the list of tools; other errors keep their context
+    let (executable_name, _) = crate::mux::resolve_tool(&config, Some(&toolchain), &cmd.tool);
+    if let Err(e) = crate::mux::try_get_executable(&config, Some(&toolchain), executable_name) {
+        let mut available = std::collections::BTreeSet::new();
+        for (name, info) in config.toolchain_fallback_iter(&toolchain) {
+            available.extend(channel::toolchain_executables(&config, &name, info)?);
+        }
+        let available = available.into_iter().collect::<Vec<_>>();
+        anyhow::bail!(
+            "{}\nTools available in `{}` and its fallbacks: {}",
+            e,
+            toolchain,
+            available.join(", ")
+        );
     }
+    let mut command = crate::mux::tool_command(&config, Some(&toolchain), &cmd.tool)?;
+    command.args(&cmd.args);
+
+    exec_command(command)
 }
 
 /// Completely remove the MoonBit installation, wiping all toolchains, configurations, and symlinks.
@@ -290,9 +602,28 @@ fn handle_nuke(_cli: &Cli, cmd: &NukeCommand) -> anyhow::Result<()> {
         }
     }
 
-    // Just delete `MOON_HOME` altogether
+    // Remove our lines from shell rc files, then delete `MOON_HOME` altogether
+    init::undo_shell_rc().context("Failed to clean up shell rc files")?;
     std::fs::remove_dir_all(&moon_home).context("Failed to remove MoonBit installation")?;
     println!("MoonBit installation removed. Good luck!");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn want_json(args: &str) -> bool {
+        args_want_json(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_args_want_json() {
+        assert!(want_json("--format json show"));
+        assert!(want_json("show --format=json --bogus"));
+        assert!(!want_json("show --format text"));
+        assert!(!want_json("run t1 moon -- --format json"));
+        assert!(!want_json("show --formatjson"));
+    }
+}
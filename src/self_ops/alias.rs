@@ -0,0 +1,200 @@
+//! Managing toolchain aliases.
+
+use anyhow::Context;
+
+use crate::{
+    config::{config_layers, merged_config, read_config, read_user_config, save_config, Config},
+    mux::real_toolchain_name,
+};
+
+#[derive(clap::Parser, Debug)]
+pub enum AliasCommandline {
+    /// Point an alias at a toolchain, like `lunik alias set stable latest`.
+    Set(SetSubcommand),
+
+    /// Remove an alias from the user config.
+    #[clap(alias = "remove")]
+    Rm(RmSubcommand),
+
+    /// List the aliases from all config layers.
+    List(ListSubcommand),
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct SetSubcommand {
+    /// The alias name
+    name: String,
+
+    /// The toolchain the alias points at
+    toolchain: String,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct RmSubcommand {
+    /// The alias name
+    name: String,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct ListSubcommand {}
+
+/// An alias in the JSON output of `lunik alias`.
+#[derive(Debug, serde::Serialize)]
+struct AliasEntry {
+    name: String,
+    /// The toolchain as written in the config
+    toolchain: String,
+    /// The toolchain after resolving channel names, if it exists
+    resolved: Option<String>,
+}
+
+pub fn entry(cli: &super::Cli, cmd: &AliasCommandline) -> anyhow::Result<()> {
+    match cmd {
+        AliasCommandline::Set(v) => handle_set(cli, v),
+        AliasCommandline::Rm(v) => handle_rm(cli, v),
+        AliasCommandline::List(v) => handle_list(cli, v),
+    }
+}
+
+/// Merge the modified user config with the other layers, and validate it.
+fn merged_with_user(config: &Config) -> anyhow::Result<Config> {
+    let merged = merged_config(&config_layers(Some(serde_json_lenient::to_value(config)?))?)?;
+    merged.validate().context("Invalid config")?;
+    Ok(merged)
+}
+
+fn alias_entry(cfg: &Config, name: &str, toolchain: &str) -> AliasEntry {
+    AliasEntry {
+        name: name.to_string(),
+        toolchain: toolchain.to_string(),
+        resolved: cfg
+            .contains_toolchain(toolchain)
+            .then(|| real_toolchain_name(cfg, toolchain).ok())
+            .flatten()
+            .map(|name| name.into_owned()),
+    }
+}
+
+/// Point the alias at the toolchain in the user config.
+fn set_alias(config: &mut Config, name: &str, toolchain: &str) {
+    config
+        .aliases
+        .insert(name.to_string(), toolchain.to_string());
+}
+
+/// Remove the alias from the user config, returning the toolchain it pointed at.
+fn remove_alias(config: &mut Config, name: &str) -> anyhow::Result<String> {
+    if config.default == name {
+        anyhow::bail!(
+            "Refusing to remove alias {} because it is the default toolchain. Use `lunik default` to set another one first.",
+            name
+        );
+    }
+    config
+        .aliases
+        .remove(name)
+        .ok_or_else(|| anyhow::anyhow!("Alias not found in the user config: {}", name))
+}
+
+fn handle_set(cli: &super::Cli, cmd: &SetSubcommand) -> anyhow::Result<()> {
+    let mut config = read_user_config()?;
+    set_alias(&mut config, &cmd.name, &cmd.toolchain);
+    let merged = merged_with_user(&config)?;
+    save_config(&config).context("Unable to save configuration")?;
+
+    // The files linked to the default toolchain follow the alias
+    if config.default == cmd.name {
+        super::channel::set_default(&mut config, &cmd.name)?;
+    }
+
+    if cli.is_json() {
+        return super::print_json(&alias_entry(&merged, &cmd.name, &cmd.toolchain));
+    }
+    println!("Alias {} set to {}", cmd.name, cmd.toolchain);
+    Ok(())
+}
+
+fn handle_rm(cli: &super::Cli, cmd: &RmSubcommand) -> anyhow::Result<()> {
+    let mut config = read_user_config()?;
+    let toolchain = remove_alias(&mut config, &cmd.name)?;
+    let merged = merged_with_user(&config)?;
+    save_config(&config).context("Unable to save configuration")?;
+
+    if cli.is_json() {
+        return super::print_json(&alias_entry(&merged, &cmd.name, &toolchain));
+    }
+    println!("Alias removed: {}", cmd.name);
+    Ok(())
+}
+
+fn handle_list(cli: &super::Cli, _cmd: &ListSubcommand) -> anyhow::Result<()> {
+    let config = read_config().context("When reading config")?;
+    let mut aliases = config.aliases.iter().collect::<Vec<_>>();
+    aliases.sort();
+
+    if cli.is_json() {
+        let entries = aliases
+            .into_iter()
+            .map(|(name, toolchain)| alias_entry(&config, name, toolchain))
+            .collect::<Vec<_>>();
+        return super::print_json(&entries);
+    }
+
+    for (name, toolchain) in aliases {
+        println!("{} -> {}", name, toolchain);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ToolchainInfo;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config
+            .toolchain
+            .insert("dev".into(), ToolchainInfo::default());
+        config
+            .toolchain
+            .insert("nightly".into(), ToolchainInfo::default());
+        config
+    }
+
+    #[test]
+    fn test_set_alias() {
+        let mut config = config();
+        set_alias(&mut config, "stable", "dev");
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            alias_entry(&config, "stable", "dev").resolved.unwrap(),
+            "dev"
+        );
+
+        set_alias(&mut config, "stable", "nightly");
+        assert_eq!(config.aliases["stable"], "nightly");
+        assert!(config.validate().is_ok());
+
+        set_alias(&mut config, "latest", "dev");
+        assert!(config.validate().is_err());
+        config.aliases.remove("latest");
+
+        set_alias(&mut config, "lts", "missing");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_remove_alias() {
+        let mut config = config();
+        set_alias(&mut config, "stable", "dev");
+        set_alias(&mut config, "edge", "nightly");
+        config.default = "stable".into();
+
+        assert!(remove_alias(&mut config, "stable").is_err());
+        assert!(remove_alias(&mut config, "missing").is_err());
+        assert_eq!(remove_alias(&mut config, "edge").unwrap(), "nightly");
+        assert!(!config.aliases.contains_key("edge"));
+        assert!(config.aliases.contains_key("stable"));
+    }
+}
@@ -1,6 +1,11 @@
 //! Toolchain management.
 
-use std::{cell::Cell, path::Path};
+use std::{
+    cell::Cell,
+    collections::BTreeSet,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use indicatif::ProgressStyle;
@@ -9,11 +14,17 @@ use tempfile::TempDir;
 
 use crate::{
     channel::{Channel, ChannelKind},
-    config::{read_config, save_config, ChannelInfo, Config, ToolchainInfo, BIN_DIR, LIB_DIR},
+    config::{
+        read_config, read_user_config, save_config, ChannelInfo, Config, ToolchainInfo, BIN_DIR,
+        LIB_DIR,
+    },
     mux::real_toolchain_name,
 };
 
-use super::symlink_self_to;
+use super::{
+    gc::{BACKUP_SUFFIX, INSTALL_TEMP_PREFIX},
+    is_lunik_shim, symlink_self_to,
+};
 
 const MOONBIT_CLI_WEB: &str = "https://cli.moonbitlang.com";
 
@@ -263,6 +274,112 @@ fn ensure_all_executables_are_linked(bin_dir: &std::path::Path) -> anyhow::Resul
     Ok(())
 }
 
+/// The file in $MOON_HOME linked to the default toolchain, instead of being a shim.
+const LSP_SERVER_FILE: &str = "bin/lsp-server.js";
+
+/// Collect the names of the executables provided by a single toolchain,
+/// including tool overrides and aliases. Names include the `.exe` suffix on Windows.
+pub fn toolchain_executables(
+    cfg: &Config,
+    name: &str,
+    info: &ToolchainInfo,
+) -> anyhow::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for tool in info.override_.keys().chain(info.aliases.keys()) {
+        names.insert(if cfg!(windows) {
+            format!("{}.exe", tool)
+        } else {
+            tool.clone()
+        });
+    }
+
+    let bin_dir = cfg.toolchain_dir(name).join(BIN_DIR);
+    if !bin_dir.is_dir() {
+        return Ok(names);
+    }
+    for entry in std::fs::read_dir(&bin_dir)
+        .with_context(|| format!("Failed to read {}", bin_dir.display()))?
+    {
+        let path = entry?.path();
+        if can_be_executable(&path) {
+            names.insert(path.file_name().unwrap().to_string_lossy().to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Collect the names of all executables provided by the configured toolchains,
+/// including tool overrides and aliases.
+fn provided_executables(cfg: &Config) -> anyhow::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for (name, info) in &cfg.toolchain {
+        names.extend(toolchain_executables(cfg, name, info)?);
+    }
+    Ok(names)
+}
+
+/// Make the shims in the bin directory match the executables provided by the
+/// configured toolchains. Missing shims are created, and lunik shims that no
+/// toolchain provides are removed. Files not linked to lunik are never touched.
+pub fn sync_shims(cfg: &Config) -> anyhow::Result<()> {
+    sync_shims_in(cfg, &crate::config::moon_bin_dir())
+}
+
+/// Like [`sync_shims`], for the shims in the given bin directory.
+pub fn sync_shims_in(cfg: &Config, moon_bin_dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(moon_bin_dir).context(format!(
+        "Failed to create the bin directory {}",
+        moon_bin_dir.display()
+    ))?;
+
+    let mut wanted = provided_executables(cfg)?;
+    // This one is linked to the default toolchain by `lunik default`
+    if let Some(lsp_server) = std::path::Path::new(LSP_SERVER_FILE).file_name() {
+        wanted.remove(&*lsp_server.to_string_lossy());
+    }
+
+    for name in &wanted {
+        let exe_path = moon_bin_dir.join(name);
+        if exe_path.exists() {
+            continue;
+        }
+        if exe_path.is_symlink() {
+            // A dangling symlink. Replace it only if it was ours.
+            if !is_lunik_shim(&exe_path) {
+                tracing::warn!("Skipping non-lunik file {}", exe_path.display());
+                continue;
+            }
+            std::fs::remove_file(&exe_path)
+                .with_context(|| format!("Failed to remove {}", exe_path.display()))?;
+        }
+        symlink_self_to(&exe_path)
+            .with_context(|| format!("Failed to create symlink {}", exe_path.display()))?;
+        tracing::info!("Linked {}", exe_path.display());
+    }
+
+    for entry in std::fs::read_dir(moon_bin_dir)
+        .with_context(|| format!("Failed to read {}", moon_bin_dir.display()))?
+    {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        let is_self = path
+            .file_stem()
+            .is_some_and(|stem| stem == crate::BINARY_NAME);
+        if is_self
+            || wanted.contains(&*file_name)
+            || path == crate::config::home_dir().join(LSP_SERVER_FILE)
+            || !is_lunik_shim(&path)
+        {
+            continue;
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale shim {}", path.display()))?;
+        tracing::info!("Removed stale shim {}", path.display());
+    }
+
+    Ok(())
+}
+
 /// Perform a full installation of the toolchain.
 fn full_install(
     config: &Config,
@@ -283,8 +400,10 @@ fn full_install(
     tracing::info!("Begin installation in channel {}", channel);
 
     // Download and unpack in a temporary directory
-    let tempdir_ =
-        TempDir::with_prefix_in(format!("lunik-install-{}", channel), target_parent_dir)?;
+    let tempdir_ = TempDir::with_prefix_in(
+        format!("{}{}", INSTALL_TEMP_PREFIX, channel),
+        target_parent_dir,
+    )?;
     let tempdir = tempdir_.path();
     tracing::debug!("Using temporary directory: {}", tempdir.display());
 
@@ -382,7 +501,7 @@ fn full_install(
     // Move to the final location
     // Rename the old directory if it exists
     let update_successful = Cell::new(false);
-    let backup_dir = target_parent_dir.join(format!("{}-backup", channel));
+    let backup_dir = target_parent_dir.join(format!("{}{}", channel, BACKUP_SUFFIX));
     // If anything fails, we will roll back the changes
     scopeguard::defer! {
         if !update_successful.get() {
@@ -469,13 +588,52 @@ pub struct AddSubcommand {
     channel: String,
 }
 
-fn handle_add(_cli: &super::Cli, cmd: &AddSubcommand) -> anyhow::Result<()> {
-    let old_config = read_config().context("When reading config")?;
-    let channel: Channel = cmd.channel.parse().context("parsing toolchain channel")?;
+fn handle_add(cli: &super::Cli, cmd: &AddSubcommand) -> anyhow::Result<()> {
+    let channel_name = add_channel(&cmd.channel, false)?;
+
+    if cli.is_json() {
+        return super::print_json(&super::OperationOutput {
+            operation: "add",
+            toolchains: vec![channel_name],
+            ..Default::default()
+        });
+    }
+    println!("Toolchain installed: {}", channel_name);
+
+    Ok(())
+}
+
+/// Add a toolchain channel to the config and install it.
+///
+/// If `allow_existing` is set, an existing channel is not an error: it is only
+/// installed again if its directory is missing.
+///
+/// Returns the normalized toolchain name, like `latest-linux-x86_64`.
+pub fn add_channel(channel: &str, allow_existing: bool) -> anyhow::Result<String> {
+    let old_config = read_user_config().context("When reading config")?;
+    let channel: Channel = channel.parse().context("parsing toolchain channel")?;
     let channel_name = channel.to_string();
+    let toolchain_root = crate::config::toolchain_root();
+    let path = crate::config::toolchain_path(&channel_name);
 
     if old_config.channels.contains_key(&channel_name) {
-        anyhow::bail!("Toolchain channel already exists: {}", cmd.channel);
+        if !allow_existing {
+            anyhow::bail!("Toolchain channel already exists: {}", channel_name);
+        }
+        if path.exists() {
+            tracing::info!("Toolchain {} is already installed", channel_name);
+            return Ok(channel_name);
+        }
+        let mut client = reqwest::blocking::Client::new();
+        full_install(
+            &old_config,
+            &mut client,
+            &channel,
+            &path,
+            &toolchain_root,
+            false,
+        )?;
+        return Ok(channel_name);
     }
 
     // Update the config
@@ -494,8 +652,6 @@ fn handle_add(_cli: &super::Cli, cmd: &AddSubcommand) -> anyhow::Result<()> {
 
     // Do the installation
     let mut client = reqwest::blocking::Client::new();
-    let toolchain_root = crate::config::toolchain_root();
-    let path = crate::config::toolchain_path(&channel_name);
     match full_install(
         &new_config,
         &mut client,
@@ -512,9 +668,7 @@ fn handle_add(_cli: &super::Cli, cmd: &AddSubcommand) -> anyhow::Result<()> {
         }
     };
 
-    println!("Toolchain installed: {}", cmd.channel);
-
-    Ok(())
+    Ok(channel_name)
 }
 
 #[derive(Debug, clap::Parser)]
@@ -523,8 +677,8 @@ pub struct UpdateSubcommand {
     channel: Vec<String>,
 }
 
-fn handle_update(_cli: &super::Cli, cmd: &UpdateSubcommand) -> anyhow::Result<()> {
-    let config = read_config().context("When reading config")?;
+fn handle_update(cli: &super::Cli, cmd: &UpdateSubcommand) -> anyhow::Result<()> {
+    let config = read_user_config().context("When reading config")?;
     let channels = if cmd.channel.is_empty() {
         config.channels.keys().cloned().collect()
     } else {
@@ -532,17 +686,29 @@ fn handle_update(_cli: &super::Cli, cmd: &UpdateSubcommand) -> anyhow::Result<()
     };
 
     let mut client = reqwest::blocking::Client::new();
-    for channel in channels {
+    for channel in &channels {
         let toolchain: Channel = channel.parse().context("parsing toolchain channel")?;
         full_install(
             &config,
             &mut client,
             &toolchain,
-            &crate::config::toolchain_path(&channel),
+            &crate::config::toolchain_path(channel),
             &crate::config::toolchain_root(),
             false,
         )?;
-        println!("Toolchain updated: {}", channel);
+        if !cli.is_json() {
+            println!("Toolchain updated: {}", channel);
+        }
+    }
+
+    sync_shims(&read_config()?).context("Failed to sync shims")?;
+
+    if cli.is_json() {
+        return super::print_json(&super::OperationOutput {
+            operation: "update",
+            toolchains: channels,
+            ..Default::default()
+        });
     }
 
     Ok(())
@@ -552,41 +718,297 @@ fn handle_update(_cli: &super::Cli, cmd: &UpdateSubcommand) -> anyhow::Result<()
 pub struct RemoveSubcommand {
     /// The toolchain to remove
     channel: String,
+
+    /// Remove the toolchain even if it is the default or the fallback of other toolchains.
+    /// Dangling fallbacks are rewritten to the removed toolchain's own fallback, or cleared.
+    #[clap(short, long)]
+    force: bool,
+
+    /// The new default toolchain to use if the removed toolchain is the default.
+    /// If not specified, you will be prompted to choose one.
+    #[clap(long)]
+    new_default: Option<String>,
 }
 
-fn handle_remove(_cli: &super::Cli, cmd: &RemoveSubcommand) -> anyhow::Result<()> {
-    let config = read_config().context("When reading config")?;
+/// Check whether `name` refers to the toolchain `real_name`, after resolving channel names.
+fn refers_to(config: &Config, name: &str, real_name: &str) -> bool {
+    real_toolchain_name(config, name).is_ok_and(|name| name == real_name)
+}
+
+/// The aliases pointing at a toolchain, which would dangle once it is removed.
+fn aliases_of(config: &Config, real_name: &str) -> Vec<String> {
+    let mut aliases = config
+        .aliases
+        .iter()
+        .filter(|(_, target)| refers_to(config, target, real_name))
+        .map(|(alias, _)| alias.clone())
+        .collect::<Vec<_>>();
+    aliases.sort();
+    aliases
+}
+
+/// References to a toolchain which come from config layers other than the user
+/// config. lunik only changes the user config, so it can't remove them.
+fn references_from_other_layers(real_name: &str) -> anyhow::Result<Vec<String>> {
+    let (merged, origins) = crate::config::merge_layers(&crate::config::config_layers(None)?);
+    let config: Config = serde_json_lenient::from_value(merged)?;
+    let user_prefix = format!("{}:", crate::config::LayerKind::User);
+    let from_other_layer = |path: &[&str]| {
+        let path = path.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        origins
+            .get(&path)
+            .filter(|origin| !origin.starts_with(&user_prefix))
+            .cloned()
+    };
+
+    let mut references = vec![];
+    if refers_to(&config, &config.default, real_name) {
+        if let Some(origin) = from_other_layer(&["default"]) {
+            references.push(format!("the default toolchain in {}", origin));
+        }
+    }
+    let mut toolchains = config.toolchain.iter().collect::<Vec<_>>();
+    toolchains.sort_by_key(|(name, _)| *name);
+    for (name, info) in toolchains {
+        let Some(fallback) = &info.fallback else {
+            continue;
+        };
+        if name != real_name && refers_to(&config, fallback, real_name) {
+            if let Some(origin) = from_other_layer(&["toolchain", name, "fallback"]) {
+                references.push(format!("the fallback of {} in {}", name, origin));
+            }
+        }
+    }
+    for alias in aliases_of(&config, real_name) {
+        if let Some(origin) = from_other_layer(&["aliases", &alias]) {
+            references.push(format!("the alias {} in {}", alias, origin));
+        }
+    }
+    Ok(references)
+}
+
+fn handle_remove(cli: &super::Cli, cmd: &RemoveSubcommand) -> anyhow::Result<()> {
+    let config = read_user_config().context("When reading config")?;
     let channel: Channel = cmd
         .channel
         .parse()
         .context("When parsing toolchain channel")?;
     let channel_name = channel.to_string();
 
+    if let Some(store) = read_config()?.toolchain_store(&channel_name) {
+        anyhow::bail!(
+            "Toolchain {} is in the read-only toolchain store {}, and cannot be removed",
+            cmd.channel,
+            store.display()
+        );
+    }
     if !config.channels.contains_key(&channel_name) {
         anyhow::bail!("Toolchain channel not found: {}", cmd.channel);
     }
 
-    let channel_path = crate::config::toolchain_path(&cmd.channel);
-    if channel_path.exists() {
-        std::fs::remove_dir_all(&channel_path)?;
+    // Even `--force` can't fix these, so the merged config would be left broken
+    let references = references_from_other_layers(&channel_name)?;
+    if !references.is_empty() {
+        anyhow::bail!(
+            "Refusing to remove toolchain {} because it is {}. Change those configs first.",
+            cmd.channel,
+            references.join("; ")
+        );
+    }
+
+    // Find everything that depends on this toolchain
+    let is_default = refers_to(&config, &config.default, &channel_name);
+    let mut dependents = config
+        .toolchain
+        .iter()
+        .filter(|(name, info)| {
+            **name != channel_name
+                && info
+                    .fallback
+                    .as_deref()
+                    .is_some_and(|fb| refers_to(&config, fb, &channel_name))
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    dependents.sort();
+
+    if (is_default || !dependents.is_empty()) && !cmd.force {
+        let mut reasons = vec![];
+        if is_default {
+            reasons.push("it is the default toolchain".to_string());
+        }
+        if !dependents.is_empty() {
+            reasons.push(format!("it is the fallback of: {}", dependents.join(", ")));
+        }
+        anyhow::bail!(
+            "Refusing to remove toolchain {} because {}. Use `--force` to remove it anyway.",
+            cmd.channel,
+            reasons.join("; ")
+        );
     }
 
+    let removed_aliases = aliases_of(&config, &channel_name);
+
     let mut config = config;
+    for alias in &removed_aliases {
+        config.aliases.remove(alias);
+    }
+    let removed = config.toolchain.remove(&channel_name);
     config.channels.remove(&channel_name);
-    config.toolchain.remove(&channel_name);
+
+    // Rewrite fallbacks pointing at the removed toolchain to its own fallback,
+    // unless that would make a toolchain its own fallback
+    let inherited_fallback = removed.and_then(|info| info.fallback);
+    let mut changed_fallbacks = vec![];
+    for name in &dependents {
+        let fallback = inherited_fallback
+            .clone()
+            .filter(|fb| !refers_to(&config, fb, name));
+        let info = config.toolchain.get_mut(name).unwrap();
+        info.fallback = fallback;
+        changed_fallbacks.push(super::ChangedFallback {
+            toolchain: name.clone(),
+            fallback: info.fallback.clone(),
+        });
+    }
+
+    // Pick and check the new default toolchain before touching the disk
+    let new_default = if is_default {
+        match &cmd.new_default {
+            Some(name) => Some(name.clone()),
+            None => prompt_new_default(&config)?,
+        }
+    } else {
+        None
+    };
+    if let Some(name) = &new_default {
+        if refers_to(&config, name, &channel_name) {
+            anyhow::bail!(
+                "The new default toolchain {} is the toolchain being removed",
+                name
+            );
+        }
+    }
+    if is_default {
+        config.default = new_default.clone().unwrap_or_default();
+    }
+    crate::config::merged_config(&crate::config::config_layers(Some(
+        serde_json_lenient::to_value(&config)?,
+    ))?)?
+    .validate()
+    .context("Invalid config after removing the toolchain")?;
+
+    // Relink and save first, so that a failure leaves the toolchain in place
+    if is_default {
+        match &new_default {
+            Some(name) => set_default(&mut config, name)?,
+            None => unlink_default(),
+        }
+    }
+
     save_config(&config)?;
 
+    let channel_path = crate::config::toolchain_path(&channel_name);
+    if channel_path.exists() {
+        std::fs::remove_dir_all(&channel_path)?;
+    }
+
+    sync_shims(&read_config()?).context("Failed to sync shims")?;
+
+    if cli.is_json() {
+        return super::print_json(&super::OperationOutput {
+            operation: "remove",
+            toolchains: vec![channel_name],
+            default: new_default,
+            changed_fallbacks,
+            removed_aliases,
+        });
+    }
+
+    for alias in &removed_aliases {
+        println!("Alias {} removed", alias);
+    }
+
+    for changed in &changed_fallbacks {
+        match &changed.fallback {
+            Some(fb) => println!("Fallback of {} changed to {}", changed.toolchain, fb),
+            None => println!("Fallback of {} cleared", changed.toolchain),
+        }
+    }
+    if is_default {
+        match &new_default {
+            Some(name) => println!("Default toolchain set to {}", name),
+            None => println!("No default toolchain is set. Use `lunik default` to set one."),
+        }
+    }
     println!("Toolchain removed: {}", cmd.channel);
 
     Ok(())
 }
 
+/// Ask the user to choose a new default toolchain. Returns `None` if there is
+/// nothing to choose from or we cannot prompt.
+fn prompt_new_default(config: &Config) -> anyhow::Result<Option<String>> {
+    let mut candidates = config.toolchain.keys().cloned().collect::<Vec<_>>();
+    if candidates.is_empty() || !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    candidates.sort();
+    let choice = inquire::Select::new("Choose the new default toolchain:", candidates).prompt()?;
+    Ok(Some(choice))
+}
+
 #[derive(Debug, clap::Parser)]
 pub struct ListSubcommand {}
 
-fn handle_list(_cli: &super::Cli, _cmd: &ListSubcommand) -> anyhow::Result<()> {
+/// A toolchain in the JSON output of `lunik channel list`.
+#[derive(Debug, serde::Serialize)]
+struct ListEntry {
+    name: String,
+    /// The channel, if the toolchain is installed from one
+    channel: Option<String>,
+    /// The host of the channel
+    host: Option<String>,
+    root_path: PathBuf,
+    /// The read-only toolchain store containing the toolchain
+    store: Option<PathBuf>,
+    fallback: Option<String>,
+    is_default: bool,
+    /// The output of `moon version`, if available
+    version: Option<String>,
+}
+
+fn handle_list(cli: &super::Cli, _cmd: &ListSubcommand) -> anyhow::Result<()> {
     let config = read_config().context("When reading config")?;
-    for name in config.toolchain.keys() {
+    let mut names = config.toolchain.keys().collect::<Vec<_>>();
+    names.sort();
+
+    if cli.is_json() {
+        let entries = names
+            .into_iter()
+            .map(|name| {
+                let info = &config.toolchain[name];
+                let channel = config
+                    .channels
+                    .contains_key(name)
+                    .then(|| name.parse::<Channel>().ok())
+                    .flatten();
+                ListEntry {
+                    name: name.clone(),
+                    channel: channel.as_ref().map(|ch| ch.channel.to_string()),
+                    host: channel.as_ref().map(|ch| ch.host.to_string()),
+                    root_path: config.toolchain_dir(name),
+                    store: config.toolchain_store(name),
+                    fallback: info.fallback.clone(),
+                    is_default: refers_to(&config, &config.default, name),
+                    version: super::show::tool_version(&config, Some(name), "moon", "version"),
+                }
+            })
+            .collect::<Vec<_>>();
+        return super::print_json(&entries);
+    }
+
+    for name in names {
         println!("{}", name);
     }
 
@@ -600,26 +1022,65 @@ pub struct DefaultSubcommand {
     toolchain: String,
 }
 
-pub fn handle_default(_cli: &super::Cli, cmd: &DefaultSubcommand) -> anyhow::Result<()> {
-    let mut config = crate::config::read_config()?;
+pub fn handle_default(cli: &super::Cli, cmd: &DefaultSubcommand) -> anyhow::Result<()> {
+    let mut config = read_user_config()?;
+
+    set_default(&mut config, &cmd.toolchain)?;
 
-    let toolchain_name = real_toolchain_name(&config, &cmd.toolchain)?;
-    config.default = toolchain_name.clone().into();
+    crate::config::save_config(&config).context("Unable to save configuration")?;
 
+    if cli.is_json() {
+        return super::print_json(&super::OperationOutput {
+            operation: "default",
+            toolchains: vec![config.default.clone()],
+            default: Some(config.default.clone()),
+            ..Default::default()
+        });
+    }
     println!("Default toolchain set to {}", cmd.toolchain);
+    Ok(())
+}
 
-    symlink_dir_to_default(&toolchain_name, "lib")?;
-    symlink_file_to_default(&toolchain_name, "bin/lsp-server.js")?;
+/// Set the default toolchain in `config`, and relink the files in $MOON_HOME to it.
+pub fn set_default(config: &mut Config, toolchain: &str) -> anyhow::Result<()> {
+    // The toolchain may come from another config layer
+    let merged = crate::config::merged_config(&crate::config::config_layers(Some(
+        serde_json_lenient::to_value(&*config)?,
+    ))?)?;
+    let toolchain_name = real_toolchain_name(&merged, toolchain)?.into_owned();
+    // Keep aliases, so that the default follows them when they are repointed
+    config.default = if merged.aliases.contains_key(toolchain) {
+        toolchain.to_string()
+    } else {
+        toolchain_name.clone()
+    };
+
+    let toolchain_dir = merged.toolchain_dir(&toolchain_name);
+    symlink_dir_to_default(&toolchain_dir, LIB_DIR)?;
+    symlink_file_to_default(&toolchain_dir, LSP_SERVER_FILE)?;
 
-    crate::config::save_config(&config).context("Unable to save configuration")?;
     Ok(())
 }
 
+/// Remove the links in $MOON_HOME pointing to the default toolchain.
+fn unlink_default() {
+    for path in [LIB_DIR, LSP_SERVER_FILE] {
+        let path = crate::config::home_dir().join(path);
+        if path.is_symlink() {
+            tracing::info!("Removing link: {}", path.display());
+            // Directory symlinks need `remove_dir` on Windows
+            let res = std::fs::remove_file(&path).or_else(|_| std::fs::remove_dir(&path));
+            if let Err(e) = res {
+                tracing::warn!("Unable to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
 /// Delete a file in $MOON_HOME and symlink it to the equivalent file in the specified toolchain
-fn symlink_file_to_default(toolchain_name: &str, file_path: &str) -> anyhow::Result<()> {
+fn symlink_file_to_default(toolchain_dir: &Path, file_path: &str) -> anyhow::Result<()> {
     let target_file = crate::config::home_dir().join(file_path);
-    let default_toolchain_path = crate::config::toolchain_path(toolchain_name);
-    let toolchain_target_file = default_toolchain_path.join(file_path);
+    let toolchain_target_file = toolchain_dir.join(file_path);
 
     // Ensure the parent directory of the target file exists
     if let Some(parent) = target_file.parent() {
@@ -667,10 +1128,9 @@ fn symlink_file_to_default(toolchain_name: &str, file_path: &str) -> anyhow::Res
 }
 
 /// Delete a directory in $MOON_HOME and symlink it to the equivalent directory in the specified toolchain
-fn symlink_dir_to_default(toolchain_name: &str, dir_path: &str) -> anyhow::Result<()> {
+fn symlink_dir_to_default(toolchain_dir: &Path, dir_path: &str) -> anyhow::Result<()> {
     let target_dir = crate::config::home_dir().join(dir_path);
-    let default_toolchain_path = crate::config::toolchain_path(toolchain_name);
-    let toolchain_target_dir = default_toolchain_path.join(dir_path);
+    let toolchain_target_dir = toolchain_dir.join(dir_path);
 
     // Ensure the target directory exists in the toolchain
     std::fs::create_dir_all(&toolchain_target_dir)
@@ -728,3 +1188,27 @@ pub fn entry(cli: &super::Cli, cmd: &ChannelCommandline) -> anyhow::Result<()> {
         ChannelCommandline::List(v) => handle_list(cli, v),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aliases_of() {
+        let channel = "latest".parse::<Channel>().unwrap().to_string();
+        let mut config = Config::default();
+        config
+            .toolchain
+            .insert(channel.clone(), ToolchainInfo::default());
+        config
+            .toolchain
+            .insert("dev".into(), ToolchainInfo::default());
+        config.aliases.insert("stable".into(), channel.clone());
+        config.aliases.insert("current".into(), "latest".into());
+        config.aliases.insert("mine".into(), "dev".into());
+
+        assert_eq!(aliases_of(&config, &channel), ["current", "stable"]);
+        assert_eq!(aliases_of(&config, "dev"), ["mine"]);
+        assert!(aliases_of(&config, "missing").is_empty());
+    }
+}
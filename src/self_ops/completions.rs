@@ -0,0 +1,210 @@
+//! Shell completions.
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+
+use crate::config::{moon_bin_dir, read_config};
+
+/// Print the completion script for the given shell.
+///
+/// For bash, zsh and fish, toolchain names are completed for `lunik` commands
+/// taking a toolchain, and for the `+toolchain` argument of the shims currently
+/// in the bin directory. Load it with e.g. `source <(lunik completions bash)`.
+#[derive(clap::Parser, Debug)]
+pub struct CompletionsSubcommand {
+    /// The shell to generate completions for
+    #[clap(value_enum)]
+    shell: Shell,
+}
+
+/// List the configured toolchains and aliases, one per line. Used by the completion scripts.
+#[derive(clap::Parser, Debug)]
+pub struct CompleteToolchainsSubcommand {}
+
+pub fn handle_complete_toolchains(
+    _cli: &super::Cli,
+    _cmd: &CompleteToolchainsSubcommand,
+) -> anyhow::Result<()> {
+    // Completions should never fail loudly
+    let Ok(cfg) = read_config() else {
+        return Ok(());
+    };
+    let mut names = cfg
+        .toolchain
+        .keys()
+        .chain(cfg.aliases.keys())
+        .collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+/// Names of the lunik shims in the bin directory.
+fn shim_names() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(moon_bin_dir()) else {
+        return vec![];
+    };
+    let mut names = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| super::is_lunik_shim(path))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .filter(|name| name != crate::BINARY_NAME && name.chars().all(is_safe_char))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Characters allowed in command names embedded in the completion scripts.
+fn is_safe_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+/// Bash completion for toolchain arguments. Wraps the generated `_lunik`.
+const BASH_DYNAMIC: &str = r#"
+_lunik_toolchains() {
+    COMPREPLY=($(compgen -P "$2" -W "$(lunik complete-toolchains 2>/dev/null)" -- "$1"))
+}
+
+_lunik_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    case "${COMP_WORDS[1]}" in
+        default|which|with|show|env|run)
+            if [[ $COMP_CWORD -eq 2 && "$cur" != -* ]]; then
+                _lunik_toolchains "$cur"
+                return
+            fi
+            ;;
+        channel)
+            case "${COMP_WORDS[2]}" in
+                remove|update|default)
+                    if [[ $COMP_CWORD -eq 3 && "$cur" != -* ]]; then
+                        _lunik_toolchains "$cur"
+                        return
+                    fi
+                    ;;
+            esac
+            ;;
+    esac
+    _lunik "$@"
+}
+
+complete -F _lunik_dynamic -o nosort -o bashdefault -o default lunik
+
+_lunik_shim() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ $COMP_CWORD -eq 1 && "$cur" == +* ]]; then
+        _lunik_toolchains "${cur#+}" +
+        return
+    fi
+    local orig="_lunik_orig_${1//[^a-zA-Z0-9_]/_}"
+    if [[ -n "${!orig}" ]]; then
+        "${!orig}" "$@"
+        return
+    fi
+    COMPREPLY=()
+}
+
+for _lunik_cmd in __LUNIK_SHIMS__; do
+    # Keep the completion the tool registered itself, if any
+    eval "_lunik_orig_${_lunik_cmd//[^a-zA-Z0-9_]/_}=$(complete -p "$_lunik_cmd" 2>/dev/null | sed -n 's/.*-F \([^ ]*\).*/\1/p')"
+    complete -F _lunik_shim -o bashdefault -o default "$_lunik_cmd"
+done
+unset _lunik_cmd
+"#;
+
+/// Zsh completion for toolchain arguments. Wraps the generated `_lunik`.
+const ZSH_DYNAMIC: &str = r#"
+_lunik_toolchains() {
+    local -a toolchains
+    toolchains=(${(f)"$(lunik complete-toolchains 2>/dev/null)"})
+    compadd -a toolchains
+}
+
+_lunik_dynamic() {
+    if [[ $PREFIX != -* ]]; then
+        case "$words[2]" in
+            default|which|with|show|env|run)
+                if (( CURRENT == 3 )); then
+                    _lunik_toolchains
+                    return
+                fi
+                ;;
+            channel)
+                if [[ "$words[3]" == (remove|update|default) ]] && (( CURRENT == 4 )); then
+                    _lunik_toolchains
+                    return
+                fi
+                ;;
+        esac
+    fi
+    _lunik "$@"
+}
+
+compdef _lunik_dynamic lunik
+
+typeset -gA _lunik_orig
+_lunik_shim() {
+    if (( CURRENT == 2 )) && [[ $PREFIX == +* ]]; then
+        compset -P '+'
+        _lunik_toolchains
+        return
+    fi
+    local orig=${_lunik_orig[$service]}
+    if [[ -n $orig ]]; then
+        $orig "$@"
+    else
+        _files
+    fi
+}
+
+for _lunik_cmd in __LUNIK_SHIMS__; do
+    # Keep the completion the tool registered itself, if any
+    if [[ -n ${_comps[$_lunik_cmd]} && ${_comps[$_lunik_cmd]} != _lunik_shim ]]; then
+        _lunik_orig[$_lunik_cmd]=${_comps[$_lunik_cmd]}
+    fi
+    compdef _lunik_shim $_lunik_cmd
+done
+unset _lunik_cmd
+"#;
+
+/// Fish completion for toolchain arguments. Completions in fish are additive.
+const FISH_DYNAMIC: &str = r#"
+complete -c lunik -n "__fish_seen_subcommand_from default which with show env run; and test (count (commandline -opc)) -eq 2" -f -a "(lunik complete-toolchains 2>/dev/null)"
+complete -c lunik -n "__fish_seen_subcommand_from channel; and __fish_seen_subcommand_from remove update default; and test (count (commandline -opc)) -eq 3" -f -a "(lunik complete-toolchains 2>/dev/null)"
+for _lunik_cmd in __LUNIK_SHIMS__
+    complete -c $_lunik_cmd -n "test (count (commandline -opc)) -eq 1; and string match -q -- '+*' (commandline -ct)" -f -a "(lunik complete-toolchains 2>/dev/null | string replace -r '^' '+')"
+end
+set -e _lunik_cmd
+"#;
+
+/// Generate the full completion script for a shell.
+fn completion_script(shell: Shell, shims: &[String]) -> anyhow::Result<String> {
+    let mut buf = vec![];
+    clap_complete::generate(
+        shell,
+        &mut super::Cli::command(),
+        crate::BINARY_NAME,
+        &mut buf,
+    );
+    let mut script = String::from_utf8(buf)?;
+
+    let dynamic = match shell {
+        Shell::Bash => Some(BASH_DYNAMIC),
+        Shell::Zsh => Some(ZSH_DYNAMIC),
+        Shell::Fish => Some(FISH_DYNAMIC),
+        _ => None,
+    };
+    if let Some(dynamic) = dynamic {
+        script.push_str(&dynamic.replace("__LUNIK_SHIMS__", &shims.join(" ")));
+    }
+
+    Ok(script)
+}
+
+pub fn handle_completions(_cli: &super::Cli, cmd: &CompletionsSubcommand) -> anyhow::Result<()> {
+    print!("{}", completion_script(cmd.shell, &shim_names())?);
+    Ok(())
+}
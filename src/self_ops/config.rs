@@ -0,0 +1,500 @@
+//! Reading and editing the config from the command line.
+
+use std::{io::IsTerminal, path::Path};
+
+use anyhow::Context;
+use serde_json_lenient::Value;
+
+use crate::config::{
+    config_bin_dir, config_layers, config_path, config_schema, merge_layers, merged_config,
+    moon_bin_dir, read_config, read_user_config, save_config, Config, LayerKind,
+};
+
+#[derive(clap::Parser, Debug)]
+pub enum ConfigCommandline {
+    /// Print the value at a dotted path, like `toolchain.dev.fallback`, merged from all config layers.
+    Get(GetSubcommand),
+
+    /// Set the value at a dotted path in the user config, like `lunik config set toolchain.dev.fallback stable`.
+    Set(SetSubcommand),
+
+    /// Remove the value at a dotted path from the user config.
+    Unset(UnsetSubcommand),
+
+    /// Open the user config in `$EDITOR`, and validate it before saving.
+    Edit(EditSubcommand),
+
+    /// Print the path of the user config file.
+    Path(PathSubcommand),
+
+    /// Print the JSON Schema of the config, for editors to validate it.
+    Schema(SchemaSubcommand),
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct GetSubcommand {
+    /// The dotted path. Quote a segment containing dots, like `toolchain."v0.1".fallback`.
+    key: String,
+
+    /// Show which config layer each value comes from.
+    #[clap(long)]
+    show_origin: bool,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct SetSubcommand {
+    /// The dotted path. Quote a segment containing dots, like `toolchain."v0.1".fallback`.
+    key: String,
+
+    /// The value. It is parsed as JSON if possible, otherwise it is used as a string.
+    value: String,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct UnsetSubcommand {
+    /// The dotted path. Quote a segment containing dots, like `toolchain."v0.1".fallback`.
+    key: String,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct EditSubcommand {}
+
+#[derive(clap::Parser, Debug)]
+pub struct PathSubcommand {}
+
+#[derive(clap::Parser, Debug)]
+pub struct SchemaSubcommand {}
+
+pub fn entry(cli: &super::Cli, cmd: &ConfigCommandline) -> anyhow::Result<()> {
+    match cmd {
+        ConfigCommandline::Get(v) => handle_get(cli, v),
+        ConfigCommandline::Set(v) => handle_set(cli, v),
+        ConfigCommandline::Unset(v) => handle_unset(cli, v),
+        ConfigCommandline::Edit(v) => handle_edit(cli, v),
+        ConfigCommandline::Path(v) => handle_path(cli, v),
+        ConfigCommandline::Schema(v) => handle_schema(cli, v),
+    }
+}
+
+/// Split a dotted path into its segments. Segments may be double-quoted to
+/// contain dots.
+fn parse_key(key: &str) -> anyhow::Result<Vec<String>> {
+    let mut segments = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut was_quoted = false;
+    for c in key.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                was_quoted = true;
+            }
+            '.' if !quoted => {
+                if current.is_empty() && !was_quoted {
+                    anyhow::bail!("Empty segment in config key `{}`", key);
+                }
+                segments.push(std::mem::take(&mut current));
+                was_quoted = false;
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        anyhow::bail!("Unterminated quote in config key `{}`", key);
+    }
+    if current.is_empty() && !was_quoted {
+        anyhow::bail!("Empty segment in config key `{}`", key);
+    }
+    segments.push(current);
+    Ok(segments)
+}
+
+/// Find the schema of the value at `path`, following `$ref`s into the definitions.
+fn schema_at<'a>(schema: &'a Value, path: &[String]) -> Option<&'a Value> {
+    let resolve = |value: &'a Value| match value.get("$ref").and_then(Value::as_str) {
+        Some(reference) => schema.pointer(reference.strip_prefix('#')?),
+        None => Some(value),
+    };
+    path.iter().try_fold(resolve(schema)?, |current, segment| {
+        let field = current
+            .get("properties")
+            .and_then(|properties| properties.get(segment))
+            .or_else(|| current.get("additionalProperties"))?;
+        resolve(field)
+    })
+}
+
+/// Parse a value from the command line according to the type of the field.
+///
+/// String fields take the value as is, so `1.0` stays a string. Other fields
+/// are parsed as JSON, falling back to a plain string.
+fn parse_value(value: &str, schema: Option<&Value>) -> Value {
+    let is_string = schema
+        .and_then(|schema| schema.get("type"))
+        .is_some_and(|ty| match ty {
+            Value::String(ty) => ty == "string",
+            Value::Array(types) => types.iter().any(|ty| ty == "string"),
+            _ => false,
+        });
+    if is_string {
+        return Value::String(value.to_string());
+    }
+    serde_json_lenient::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+fn get_path<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(value, |value, segment| value.as_object()?.get(segment))
+}
+
+/// Set the value at `path`, creating the objects along the way.
+fn set_path(value: &mut Value, path: &[String], new_value: Value) -> anyhow::Result<()> {
+    let (last, parents) = path.split_last().expect("paths are never empty");
+    let mut current = value;
+    for (i, segment) in parents.iter().enumerate() {
+        let object = current
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not an object", path[..i].join(".")))?;
+        current = object
+            .entry(segment.clone())
+            .and_modify(|v| {
+                if v.is_null() {
+                    *v = Value::Object(Default::default());
+                }
+            })
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+    let object = current
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("`{}` is not an object", parents.join(".")))?;
+    object.insert(last.clone(), new_value);
+    Ok(())
+}
+
+/// Remove the value at `path`. Returns the removed value, if any.
+fn unset_path(value: &mut Value, path: &[String]) -> Option<Value> {
+    let (last, parents) = path.split_last().expect("paths are never empty");
+    let mut current = value;
+    for segment in parents {
+        current = current.as_object_mut()?.get_mut(segment)?;
+    }
+    current.as_object_mut()?.remove(last)
+}
+
+/// Convert the edited user config back to a config, and check that it is
+/// valid when merged with the other layers.
+fn value_to_config(value: Value) -> anyhow::Result<Config> {
+    let config: Config = serde_json_lenient::from_value(value.clone()).context("Invalid config")?;
+    let merged = merged_config(&config_layers(Some(value))?).context("Invalid config")?;
+    merged.validate().context("Invalid config")?;
+    Ok(config)
+}
+
+/// The top-level keys whose changes can change the shims: the tools of the
+/// toolchains, and the directory of the shims.
+const SHIM_KEYS: &[&str] = &["toolchain", "aliases", "bin_dir"];
+
+/// Sync the shims after the config changed. If the bin directory changed, the
+/// shims are moved out of `old_bin_dir`.
+fn resync_shims(old_bin_dir: &Path) -> anyhow::Result<()> {
+    let cfg = read_config()?;
+    let bin_dir = config_bin_dir(&cfg);
+    if bin_dir != old_bin_dir && old_bin_dir.is_dir() {
+        // No toolchain provides shims in the old directory anymore
+        super::channel::sync_shims_in(&Config::default(), old_bin_dir)
+            .context("Failed to remove the shims from the old bin directory")?;
+    }
+    super::channel::sync_shims_in(&cfg, &bin_dir).context("Failed to sync shims")
+}
+
+/// Remove the fields of the config not known by lunik.
+fn strip_unknown_fields(config: &mut Config) {
+    config.extra.clear();
+    for info in config.toolchain.values_mut() {
+        info.extra.clear();
+    }
+    for info in config.channels.values_mut() {
+        info.extra.clear();
+    }
+}
+
+fn handle_get(cli: &super::Cli, cmd: &GetSubcommand) -> anyhow::Result<()> {
+    let path = parse_key(&cmd.key)?;
+    if cmd.show_origin {
+        return print_origins(cli, cmd, &path);
+    }
+    let config = serde_json_lenient::to_value(read_config()?)?;
+
+    let value = get_path(&config, &path).unwrap_or(&Value::Null);
+    if cli.is_json() {
+        return super::print_json(value);
+    }
+    match value {
+        Value::Null => anyhow::bail!("Config key `{}` is not set", cmd.key),
+        Value::String(s) => println!("{}", s),
+        value => println!("{}", serde_json_lenient::to_string_pretty(value)?),
+    }
+    Ok(())
+}
+
+/// Print the values under `path` with the layers they come from.
+fn print_origins(cli: &super::Cli, cmd: &GetSubcommand, path: &[String]) -> anyhow::Result<()> {
+    let (merged, origins) = merge_layers(&config_layers(None)?);
+    let values = origins
+        .iter()
+        .filter(|(p, _)| p.starts_with(path))
+        .map(|(p, origin)| (p.join("."), get_path(&merged, p).unwrap(), origin))
+        .collect::<Vec<_>>();
+
+    if cli.is_json() {
+        let values = values
+            .into_iter()
+            .map(|(key, value, origin)| {
+                serde_json_lenient::json!({ "key": key, "value": value, "origin": origin })
+            })
+            .collect::<Vec<_>>();
+        return super::print_json(&values);
+    }
+    if values.is_empty() {
+        anyhow::bail!("Config key `{}` is not set", cmd.key);
+    }
+    for (key, value, origin) in values {
+        println!("{}\t{} = {}", origin, key, value);
+    }
+    Ok(())
+}
+
+fn handle_set(cli: &super::Cli, cmd: &SetSubcommand) -> anyhow::Result<()> {
+    let path = parse_key(&cmd.key)?;
+    let old_bin_dir = moon_bin_dir();
+    let schema = config_schema();
+    let new_value = parse_value(&cmd.value, schema_at(&schema, &path));
+    let mut value = serde_json_lenient::to_value(read_user_config()?)?;
+
+    set_path(&mut value, &path, new_value.clone())?;
+    let mut config = value_to_config(value)?;
+
+    // Unknown fields are kept in the config, so check the value survived without them
+    let mut known = config.clone();
+    strip_unknown_fields(&mut known);
+    let saved = serde_json_lenient::to_value(&known)?;
+    if get_path(&saved, &path) != Some(&new_value) {
+        anyhow::bail!("Unknown config key `{}`", cmd.key);
+    }
+
+    // Changing the default also needs relinking
+    if path == ["default"] && !config.default.is_empty() {
+        let default = config.default.clone();
+        super::channel::set_default(&mut config, &default)?;
+    }
+
+    save_config(&config)?;
+    if SHIM_KEYS.contains(&path[0].as_str()) {
+        resync_shims(&old_bin_dir)?;
+    }
+
+    if cli.is_json() {
+        return super::print_json(&serde_json_lenient::json!({
+            "key": cmd.key,
+            "value": new_value,
+        }));
+    }
+    println!("Set {} to {}", cmd.key, new_value);
+    warn_if_overridden(&path)?;
+    Ok(())
+}
+
+fn handle_unset(cli: &super::Cli, cmd: &UnsetSubcommand) -> anyhow::Result<()> {
+    let path = parse_key(&cmd.key)?;
+    let old_bin_dir = moon_bin_dir();
+    let mut value = serde_json_lenient::to_value(read_user_config()?)?;
+
+    match unset_path(&mut value, &path) {
+        None | Some(Value::Null) => anyhow::bail!("Config key `{}` is not set", cmd.key),
+        Some(_) => {}
+    }
+    let config = value_to_config(value)?;
+
+    save_config(&config)?;
+    if SHIM_KEYS.contains(&path[0].as_str()) {
+        resync_shims(&old_bin_dir)?;
+    }
+
+    if cli.is_json() {
+        return super::print_json(&serde_json_lenient::json!({
+            "key": cmd.key,
+            "value": Value::Null,
+        }));
+    }
+    println!("Unset {}", cmd.key);
+    Ok(())
+}
+
+/// Tell the user if a value in the user config is overridden by a layer of
+/// higher precedence.
+fn warn_if_overridden(path: &[String]) -> anyhow::Result<()> {
+    let layers = config_layers(None)?;
+    let (_, origins) = merge_layers(&layers);
+    let user_origin = layers
+        .iter()
+        .find(|layer| layer.kind == LayerKind::User)
+        .map(|layer| layer.to_string());
+    for (p, origin) in origins.iter().filter(|(p, _)| p.starts_with(path)) {
+        if Some(origin) != user_origin.as_ref() {
+            eprintln!("Note: {} is overridden by {}", p.join("."), origin);
+        }
+    }
+    Ok(())
+}
+
+/// The command used to edit files: `$VISUAL`, `$EDITOR` or a platform default.
+fn editor_command() -> Vec<String> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    editor.split_whitespace().map(|s| s.to_string()).collect()
+}
+
+fn handle_edit(_cli: &super::Cli, _cmd: &EditSubcommand) -> anyhow::Result<()> {
+    let config_path = config_path();
+    let original = std::fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read {}", config_path.display()))?;
+    let original_value = serde_json_lenient::from_str::<Value>(&original).ok();
+    let old_bin_dir = moon_bin_dir();
+
+    // Edit a copy, so that an invalid config is never saved
+    let temp = tempfile::Builder::new()
+        .prefix("lunik-")
+        .suffix(".json")
+        .tempfile()
+        .context("Failed to create temporary file")?;
+    std::fs::write(temp.path(), &original)?;
+
+    let editor = editor_command();
+    loop {
+        let status = std::process::Command::new(&editor[0])
+            .args(&editor[1..])
+            .arg(temp.path())
+            .status()
+            .with_context(|| format!("Failed to run editor `{}`", editor.join(" ")))?;
+        if !status.success() {
+            anyhow::bail!("Editor exited with {}; the config is unchanged", status);
+        }
+
+        let edited = std::fs::read_to_string(temp.path())?;
+        if edited == original {
+            println!("No changes made.");
+            return Ok(());
+        }
+
+        let res = serde_json_lenient::from_str::<Value>(&edited)
+            .context("Invalid config")
+            .and_then(|value| value_to_config(value.clone()).map(|_| value));
+        match res {
+            Ok(value) => {
+                crate::config::write_atomically(&config_path, edited.as_bytes())
+                    .with_context(|| format!("Failed to write {}", config_path.display()))?;
+                let shims_changed = SHIM_KEYS
+                    .iter()
+                    .any(|key| original_value.as_ref().and_then(|v| v.get(key)) != value.get(key));
+                if shims_changed {
+                    resync_shims(&old_bin_dir)?;
+                }
+                println!("Config saved.");
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{:#}", e);
+                if !std::io::stdin().is_terminal()
+                    || !inquire::Confirm::new("Edit again?")
+                        .with_default(true)
+                        .prompt()?
+                {
+                    anyhow::bail!("The config is unchanged");
+                }
+            }
+        }
+    }
+}
+
+fn handle_path(cli: &super::Cli, _cmd: &PathSubcommand) -> anyhow::Result<()> {
+    let path = config_path();
+    if cli.is_json() {
+        return super::print_json(&serde_json_lenient::json!({ "path": path }));
+    }
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn handle_schema(_cli: &super::Cli, _cmd: &SchemaSubcommand) -> anyhow::Result<()> {
+    super::print_json(&config_schema())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(
+            parse_key("toolchain.dev.fallback").unwrap(),
+            ["toolchain", "dev", "fallback"]
+        );
+        assert_eq!(
+            parse_key("toolchain.\"v0.1\".root_path").unwrap(),
+            ["toolchain", "v0.1", "root_path"]
+        );
+        assert_eq!(parse_key("default").unwrap(), ["default"]);
+        assert!(parse_key("toolchain..fallback").is_err());
+        assert!(parse_key("toolchain.").is_err());
+        assert!(parse_key("toolchain.\"dev").is_err());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        let schema = config_schema();
+        let parse = |key: &str, value: &str| {
+            parse_value(value, schema_at(&schema, &parse_key(key).unwrap()))
+        };
+        assert_eq!(
+            parse("toolchain.dev.fallback", "1.0"),
+            Value::String("1.0".into())
+        );
+        assert_eq!(parse("default", "true"), Value::String("true".into()));
+        assert_eq!(
+            parse("toolchain.dev.env.MOON_DEBUG", "1"),
+            Value::String("1".into())
+        );
+        assert_eq!(parse("version", "1"), serde_json_lenient::json!(1));
+        assert_eq!(
+            parse("toolchain.dev.args_prefix.moon", r#"["-q"]"#),
+            serde_json_lenient::json!(["-q"])
+        );
+        assert_eq!(
+            parse("toolchain_stores", "/opt"),
+            Value::String("/opt".into())
+        );
+    }
+
+    #[test]
+    fn test_set_unset_path() {
+        let mut value = serde_json_lenient::json!({ "toolchain": {}, "default": "" });
+        let path = parse_key("toolchain.dev.fallback").unwrap();
+        set_path(&mut value, &path, Value::String("stable".into())).unwrap();
+        assert_eq!(
+            get_path(&value, &path),
+            Some(&Value::String("stable".into()))
+        );
+
+        assert!(set_path(&mut value, &parse_key("default.x").unwrap(), Value::Null).is_err());
+
+        assert_eq!(
+            unset_path(&mut value, &path),
+            Some(Value::String("stable".into()))
+        );
+        assert_eq!(get_path(&value, &path), None);
+        assert_eq!(unset_path(&mut value, &path), None);
+    }
+}
@@ -0,0 +1,324 @@
+//! Printing the environment of a toolchain for shells.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    config::{moon_bin_dir, read_config, MOON_CORE_OVERRIDE_ENV_NAME, MOON_HOME_ENV_NAME},
+    mux::{
+        resolve_toolchain, toolchain_environment, LUNIK_CORE_OVERRIDE_ENV_NAME,
+        LUNIK_TOOLCHAIN_ENV_NAME,
+    },
+};
+
+/// Print the environment of a toolchain, to activate it in a shell session.
+///
+/// Example: `eval "$(lunik env dev)"`
+#[derive(clap::Parser, Debug)]
+pub struct EnvSubcommand {
+    /// The toolchain to use. If not specified, it is resolved like in a shim.
+    toolchain: Option<String>,
+
+    /// The syntax of the output.
+    #[clap(long, value_enum, default_value_t = EnvShell::Bash)]
+    shell: EnvShell,
+
+    /// Print the script to deactivate the toolchain instead.
+    #[clap(long)]
+    unset: bool,
+
+    /// Only print `MOON_HOME` and `MOON_CORE_OVERRIDE` for the current directory.
+    /// Used by the shell hook installed with `lunik init --hook`.
+    #[clap(long, conflicts_with_all = ["toolchain", "unset"])]
+    hook: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvShell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+    Json,
+}
+
+/// Quote a string for a POSIX shell.
+pub fn quote_posix(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quote a string for fish.
+pub fn quote_fish(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Quote a string for PowerShell.
+pub fn quote_powershell(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Generate a line that sets an environment variable.
+pub fn export_line(shell: EnvShell, name: &str, value: &str) -> String {
+    match shell {
+        EnvShell::Bash | EnvShell::Zsh => format!("export {}={}", name, quote_posix(value)),
+        EnvShell::Fish => format!("set -gx {} {}", name, quote_fish(value)),
+        EnvShell::Powershell => format!("$env:{} = {}", name, quote_powershell(value)),
+        EnvShell::Json => unreachable!("JSON output is not line-based"),
+    }
+}
+
+/// Generate a line that removes an environment variable.
+pub fn unset_line(shell: EnvShell, name: &str) -> String {
+    match shell {
+        EnvShell::Bash | EnvShell::Zsh => format!("unset {}", name),
+        EnvShell::Fish => format!("set -e {}", name),
+        EnvShell::Powershell => {
+            format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name)
+        }
+        EnvShell::Json => unreachable!("JSON output is not line-based"),
+    }
+}
+
+/// Generate a line that sets `PATH` to the given list of directories.
+fn path_line(shell: EnvShell, paths: &[PathBuf]) -> anyhow::Result<String> {
+    if shell == EnvShell::Fish {
+        // `PATH` is a list in fish
+        let paths = paths
+            .iter()
+            .map(|p| quote_fish(&p.to_string_lossy()))
+            .collect::<Vec<_>>();
+        return Ok(format!("set -gx PATH {}", paths.join(" ")));
+    }
+    let joined = std::env::join_paths(paths).context("Unable to join PATH")?;
+    Ok(export_line(shell, "PATH", &joined.to_string_lossy()))
+}
+
+/// Set to the bin directory when `lunik env` added it to `PATH`, so that
+/// `lunik env --unset` removes only what was added.
+pub const ENV_PATH_ENV_NAME: &str = "LUNIK_ENV_PATH";
+
+/// Compute the new `PATH` for activation, if the bin directory must be added.
+fn activated_path(current: &OsStr, bin_dir: &Path) -> Option<Vec<PathBuf>> {
+    let paths = std::env::split_paths(current).collect::<Vec<_>>();
+    if paths.iter().any(|p| p == bin_dir) {
+        return None;
+    }
+    Some(
+        std::iter::once(bin_dir.to_path_buf())
+            .chain(paths)
+            .collect(),
+    )
+}
+
+/// Compute the new `PATH` for deactivation, removing the directory added by
+/// activation, if any.
+fn deactivated_path(current: &OsStr, added: Option<&OsStr>) -> Option<Vec<PathBuf>> {
+    let added = Path::new(added?);
+    let mut paths = std::env::split_paths(current).collect::<Vec<_>>();
+    let index = paths.iter().position(|p| p == added)?;
+    paths.remove(index);
+    Some(paths)
+}
+
+/// Variables removed by `lunik env --unset`. `LUNIK_HOME` is kept, since it
+/// might have been set by the user to locate the installation.
+pub const UNSET_VAR_NAMES: &[&str] = &[
+    LUNIK_TOOLCHAIN_ENV_NAME,
+    MOON_HOME_ENV_NAME,
+    MOON_CORE_OVERRIDE_ENV_NAME,
+    LUNIK_CORE_OVERRIDE_ENV_NAME,
+    ENV_PATH_ENV_NAME,
+];
+
+/// Generate the script which activates the given variables and `PATH`.
+pub fn activate_script(
+    shell: EnvShell,
+    envs: &[(&'static str, OsString)],
+) -> anyhow::Result<String> {
+    let bin_dir = moon_bin_dir();
+    let path = activated_path(&std::env::var_os("PATH").unwrap_or_default(), &bin_dir);
+    let mut envs = envs.to_vec();
+    if path.is_some() {
+        envs.push((ENV_PATH_ENV_NAME, bin_dir.into()));
+    }
+
+    if shell == EnvShell::Json {
+        let mut map = serde_json_lenient::Map::new();
+        for (name, value) in &envs {
+            map.insert(name.to_string(), value.to_string_lossy().into());
+        }
+        if let Some(path) = path {
+            let path = std::env::join_paths(path).context("Unable to join PATH")?;
+            map.insert("PATH".into(), path.to_string_lossy().into());
+        }
+        return Ok(serde_json_lenient::to_string_pretty(&map)?);
+    }
+
+    let mut lines = envs
+        .iter()
+        .map(|(name, value)| export_line(shell, name, &value.to_string_lossy()))
+        .collect::<Vec<_>>();
+    if let Some(path) = path {
+        lines.push(path_line(shell, &path)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Generate the script which removes everything set by [`activate_script`].
+pub fn deactivate_script(shell: EnvShell) -> anyhow::Result<String> {
+    let path = deactivated_path(
+        &std::env::var_os("PATH").unwrap_or_default(),
+        std::env::var_os(ENV_PATH_ENV_NAME).as_deref(),
+    );
+    if shell == EnvShell::Json {
+        let mut map = serde_json_lenient::Map::new();
+        for name in UNSET_VAR_NAMES {
+            map.insert(name.to_string(), serde_json_lenient::Value::Null);
+        }
+        if let Some(path) = path {
+            let path = std::env::join_paths(path).context("Unable to join PATH")?;
+            map.insert("PATH".into(), path.to_string_lossy().into());
+        }
+        return Ok(serde_json_lenient::to_string_pretty(&map)?);
+    }
+
+    let mut lines = UNSET_VAR_NAMES
+        .iter()
+        .map(|name| unset_line(shell, name))
+        .collect::<Vec<_>>();
+    if let Some(path) = path {
+        lines.push(path_line(shell, &path)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Variables updated by the shell hook on directory changes.
+const HOOK_VAR_NAMES: &[&str] = &[
+    MOON_HOME_ENV_NAME,
+    MOON_CORE_OVERRIDE_ENV_NAME,
+    LUNIK_CORE_OVERRIDE_ENV_NAME,
+];
+
+/// Generate the script run by the shell hook. If the toolchain cannot be
+/// resolved, the variables are removed so that the shims decide by themselves.
+fn hook_script(shell: EnvShell) -> anyhow::Result<String> {
+    let envs = read_config().and_then(|cfg| {
+        let (toolchain, _) = resolve_toolchain(None);
+        toolchain_environment(&cfg, toolchain.as_deref(), false)
+    });
+    let envs = match envs {
+        Ok(envs) => envs
+            .into_iter()
+            .filter(|(name, _)| HOOK_VAR_NAMES.contains(name))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            tracing::debug!("Unable to resolve toolchain for hook: {:?}", e);
+            vec![]
+        }
+    };
+
+    if shell == EnvShell::Json {
+        let mut map = serde_json_lenient::Map::new();
+        for name in HOOK_VAR_NAMES {
+            let value = envs
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.to_string_lossy().into())
+                .unwrap_or(serde_json_lenient::Value::Null);
+            map.insert(name.to_string(), value);
+        }
+        return Ok(serde_json_lenient::to_string_pretty(&map)?);
+    }
+
+    let lines = HOOK_VAR_NAMES
+        .iter()
+        .map(|name| match envs.iter().find(|(n, _)| n == name) {
+            Some((_, value)) => export_line(shell, name, &value.to_string_lossy()),
+            None => unset_line(shell, name),
+        })
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n"))
+}
+
+pub fn handle_env(_cli: &super::Cli, cmd: &EnvSubcommand) -> anyhow::Result<()> {
+    if cmd.hook {
+        println!("{}", hook_script(cmd.shell)?);
+        return Ok(());
+    }
+
+    if cmd.unset {
+        println!("{}", deactivate_script(cmd.shell)?);
+        return Ok(());
+    }
+
+    let cfg = read_config()?;
+    let (toolchain, _) = resolve_toolchain(cmd.toolchain.as_deref());
+    // Always set the core library, so that switching toolchains in the same
+    // session does not keep the core of the previous one.
+    let envs = toolchain_environment(&cfg, toolchain.as_deref(), false)?;
+    println!("{}", activate_script(cmd.shell, &envs)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(quote_posix("a'b"), r"'a'\''b'");
+        assert_eq!(quote_fish(r"a'b\c"), r"'a\'b\\c'");
+        assert_eq!(quote_powershell("a'b"), "'a''b'");
+    }
+
+    #[test]
+    fn test_path_changes() {
+        let join = |paths: &[&str]| std::env::join_paths(paths).unwrap();
+        let split = |path: OsString| std::env::split_paths(&path).collect::<Vec<_>>();
+        let bin = Path::new("/home/u/.moon/bin");
+
+        // Added only if missing, e.g. when `lunik init` already added it
+        assert_eq!(
+            activated_path(&join(&["/usr/bin"]), bin),
+            Some(split(join(&["/home/u/.moon/bin", "/usr/bin"])))
+        );
+        assert_eq!(
+            activated_path(&join(&["/usr/bin", "/home/u/.moon/bin"]), bin),
+            None
+        );
+
+        // Only the added entry is removed
+        assert_eq!(
+            deactivated_path(
+                &join(&["/home/u/.moon/bin", "/usr/bin", "/home/u/.moon/bin"]),
+                Some(bin.as_os_str())
+            ),
+            Some(split(join(&["/usr/bin", "/home/u/.moon/bin"])))
+        );
+        assert_eq!(
+            deactivated_path(&join(&["/usr/bin", "/home/u/.moon/bin"]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_export_line() {
+        assert_eq!(
+            export_line(EnvShell::Bash, "MOON_HOME", "/x y"),
+            "export MOON_HOME='/x y'"
+        );
+        assert_eq!(
+            export_line(EnvShell::Fish, "MOON_HOME", "/x y"),
+            "set -gx MOON_HOME '/x y'"
+        );
+        assert_eq!(
+            export_line(EnvShell::Powershell, "MOON_HOME", "/x y"),
+            "$env:MOON_HOME = '/x y'"
+        );
+    }
+}
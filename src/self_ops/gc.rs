@@ -0,0 +1,282 @@
+//! Garbage collection of files left behind by lunik.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::config::{moon_bin_dir, read_config, toolchain_root, Config};
+
+/// Prefix of the temporary directories created during installation.
+pub const INSTALL_TEMP_PREFIX: &str = "lunik-install-";
+/// Suffix of the backup directories created during installation.
+pub const BACKUP_SUFFIX: &str = "-backup";
+
+/// Installation leftovers younger than this may belong to a running installation.
+const INSTALL_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Remove orphaned toolchain directories, stale installation leftovers and dangling shims.
+#[derive(clap::Parser, Debug)]
+pub struct GcSubcommand {
+    /// Only report what would be removed.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Skip the confirmation prompt.
+    #[clap(short, long)]
+    yes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GarbageKind {
+    /// A toolchain directory not referenced by the config
+    OrphanedToolchain,
+    /// A temporary directory left by an interrupted installation
+    InstallTemp,
+    /// A backup directory left by an interrupted installation
+    Backup,
+    /// A symlink in the bin directory whose target no longer exists
+    DanglingShim,
+}
+
+impl std::fmt::Display for GarbageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GarbageKind::OrphanedToolchain => write!(f, "orphaned toolchain"),
+            GarbageKind::InstallTemp => write!(f, "installation temp dir"),
+            GarbageKind::Backup => write!(f, "installation backup"),
+            GarbageKind::DanglingShim => write!(f, "dangling shim"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Garbage {
+    kind: GarbageKind,
+    path: PathBuf,
+    size: u64,
+}
+
+/// A leftover which looks like garbage but is kept.
+#[derive(Debug)]
+struct Kept {
+    path: PathBuf,
+    reason: String,
+}
+
+/// Collect all toolchain directories which are referenced by the config.
+fn referenced_toolchain_dirs(cfg: &Config) -> Vec<PathBuf> {
+    cfg.toolchain
+        .keys()
+        .map(|name| cfg.toolchain_dir(name))
+        .collect()
+}
+
+/// Whether the path was modified within the grace period of a running installation.
+fn is_recent(path: &Path) -> bool {
+    path.symlink_metadata()
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age < INSTALL_GRACE_PERIOD)
+        })
+}
+
+/// Why an installation leftover must be kept, if it must.
+fn keep_reason(root: &Path, file_name: &str, path: &Path) -> Option<String> {
+    if let Some(toolchain) = file_name.strip_suffix(BACKUP_SUFFIX) {
+        // An interrupted update may have left the backup as the only copy
+        if !root.join(toolchain).exists() {
+            return Some(format!(
+                "the only copy of toolchain {}; rename it to {} to restore it",
+                toolchain,
+                root.join(toolchain).display()
+            ));
+        }
+    }
+    if is_recent(path) {
+        return Some("it may belong to an installation in progress".to_string());
+    }
+    None
+}
+
+fn find_garbage(cfg: &Config) -> anyhow::Result<(Vec<Garbage>, Vec<Kept>)> {
+    let mut garbage = vec![];
+    let mut kept = vec![];
+
+    let root = toolchain_root();
+    if root.is_dir() {
+        let referenced = referenced_toolchain_dirs(cfg);
+        for entry in std::fs::read_dir(&root)
+            .with_context(|| format!("Failed to read {}", root.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            if referenced.contains(&path) {
+                continue;
+            }
+
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let kind = if file_name.starts_with(INSTALL_TEMP_PREFIX) {
+                GarbageKind::InstallTemp
+            } else if file_name.ends_with(BACKUP_SUFFIX) {
+                GarbageKind::Backup
+            } else if entry.file_type()?.is_dir() {
+                GarbageKind::OrphanedToolchain
+            } else {
+                continue;
+            };
+            if kind != GarbageKind::OrphanedToolchain {
+                if let Some(reason) = keep_reason(&root, &file_name, &path) {
+                    kept.push(Kept { path, reason });
+                    continue;
+                }
+            }
+            garbage.push(Garbage {
+                kind,
+                size: dir_size(&path),
+                path,
+            });
+        }
+    }
+
+    garbage.extend(dangling_shims(&moon_bin_dir())?);
+
+    Ok((garbage, kept))
+}
+
+/// Find the shims in the bin directory whose target no longer exists. Other
+/// broken links are left alone, as the bin directory may be shared.
+fn dangling_shims(bin_dir: &Path) -> anyhow::Result<Vec<Garbage>> {
+    let mut garbage = vec![];
+    if !bin_dir.is_dir() {
+        return Ok(garbage);
+    }
+    for entry in std::fs::read_dir(bin_dir)
+        .with_context(|| format!("Failed to read {}", bin_dir.display()))?
+    {
+        let path = entry?.path();
+        // `exists` follows the symlink, so a symlink that doesn't exist is dangling
+        if super::is_lunik_shim(&path) && !path.exists() {
+            garbage.push(Garbage {
+                kind: GarbageKind::DanglingShim,
+                size: 0,
+                path,
+            });
+        }
+    }
+    Ok(garbage)
+}
+
+/// Get the total size of the files under `path`. Errors are ignored.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(meta) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| dir_size(&entry.path()))
+        .sum()
+}
+
+/// Format a byte count in a human-readable way.
+pub fn human_size(bytes: u64) -> String {
+    indicatif::HumanBytes(bytes).to_string()
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_symlink() || !path.is_dir() {
+        std::fs::remove_file(path)
+    } else {
+        std::fs::remove_dir_all(path)
+    }
+}
+
+pub fn handle_gc(_cli: &super::Cli, cmd: &GcSubcommand) -> anyhow::Result<()> {
+    let cfg = read_config().context("When reading config")?;
+    let (garbage, kept) = find_garbage(&cfg)?;
+
+    for k in &kept {
+        println!("Keeping {}: {}", k.path.display(), k.reason);
+    }
+    if garbage.is_empty() {
+        println!("Nothing to clean up.");
+        return Ok(());
+    }
+
+    let total: u64 = garbage.iter().map(|g| g.size).sum();
+    println!("The following items will be removed:");
+    for g in &garbage {
+        println!(
+            "  {:>10}  {} ({})",
+            human_size(g.size),
+            g.path.display(),
+            g.kind
+        );
+    }
+    println!("Total: {}", human_size(total));
+
+    if cmd.dry_run {
+        return Ok(());
+    }
+
+    if !cmd.yes
+        && !inquire::Confirm::new("Remove these items?")
+            .with_default(false)
+            .prompt()?
+    {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let mut any_failed = false;
+    for g in &garbage {
+        match remove_path(&g.path) {
+            Ok(()) => tracing::info!("Removed {}", g.path.display()),
+            Err(e) => {
+                eprintln!("Failed to remove {}: {}", g.path.display(), e);
+                any_failed = true;
+            }
+        }
+    }
+
+    if any_failed {
+        anyhow::bail!("Some items failed to be removed");
+    }
+    println!("Freed {}", human_size(total));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dangling_shims() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let bin_dir = dir.path().join("bin");
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        let lunik = dir.path().join("old").join(crate::BINARY_NAME);
+        symlink(&lunik, bin_dir.join("moon")).unwrap();
+        symlink(dir.path().join("missing"), bin_dir.join("user-link")).unwrap();
+        std::fs::write(bin_dir.join("other"), "").unwrap();
+        symlink(bin_dir.join("other"), bin_dir.join("live-link")).unwrap();
+
+        let garbage = dangling_shims(&bin_dir).unwrap();
+        let paths = garbage.iter().map(|g| &g.path).collect::<Vec<_>>();
+        assert_eq!(paths, [&bin_dir.join("moon")]);
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
-    borrow::Cow,
-    io::Write,
+    io::IsTerminal,
     path::{Path, PathBuf},
 };
 
 use anyhow::Context;
 
-use crate::config::{home_dir, lunik_dir, moon_bin_dir};
+use super::env::{quote_fish, quote_posix, quote_powershell};
+use crate::config::{
+    home_dir, lunik_dir, moon_bin_dir, read_config, read_user_config, save_config,
+    LUNIK_BIN_DIR_ENV_NAME,
+};
 
 /// Performs all initialization and installation steps of lunik.
 #[derive(clap::Parser, Debug)]
@@ -17,107 +20,570 @@ pub struct InitSubcommand {
 
     /// Do not automatically add the bin directory to PATH. If neither `auto` nor `no-auto` is
     /// specified, you will be prompted to choose.
-    #[clap(long)]
+    #[clap(long, visible_alias = "no-modify-path")]
     no_auto: bool,
 
-    /// Add to the specified shell. If not specified, the shell will be detected.
+    /// Never prompt, answering yes to every question. Prompts are also skipped
+    /// when stdin is not a terminal, answering no instead.
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Install this toolchain channel. Can be specified multiple times.
+    /// Already installed channels are skipped.
+    #[clap(long = "toolchain", value_name = "CHANNEL")]
+    toolchains: Vec<String>,
+
+    /// Set the default toolchain.
+    #[clap(long, value_name = "TOOLCHAIN")]
+    default: Option<String>,
+
+    /// Install lunik and its shims into this directory instead of `~/.moon/bin`.
+    /// It is saved as `bin_dir` in the config for later lunik invocations.
+    #[clap(long)]
+    bin_dir: Option<PathBuf>,
+
+    /// Add to the specified shell: bash, zsh, fish, sh, pwsh, nu, elvish or xonsh.
+    /// If not specified, the shell will be detected.
     #[clap(long)]
     shell: Option<String>,
+
+    /// Also install a shell hook that updates `MOON_HOME` and `MOON_CORE_OVERRIDE`
+    /// for the toolchain of the current directory, whenever it changes.
+    #[clap(long)]
+    hook: bool,
+
+    /// Remove the lines added by lunik from all shell rc files, and exit.
+    #[clap(
+        long,
+        conflicts_with_all = ["auto", "no_auto", "hook", "toolchains", "default", "bin_dir"]
+    )]
+    undo: bool,
 }
 
 pub fn handle_init(cmd: &InitSubcommand) -> anyhow::Result<()> {
+    if cmd.undo {
+        return undo_shell_rc();
+    }
+
+    if let Some(bin_dir) = &cmd.bin_dir {
+        let bin_dir = std::path::absolute(bin_dir).context("Invalid bin dir")?;
+        // Nothing else is running yet, so it is fine to change our environment
+        std::env::set_var(LUNIK_BIN_DIR_ENV_NAME, &bin_dir);
+    }
+
     // First, create home dir, lunik dir and bin dir
     std::fs::create_dir_all(home_dir()).context("Failed to create home dir")?;
     std::fs::create_dir_all(moon_bin_dir()).context("Failed to create moon binary dir")?;
     std::fs::create_dir_all(lunik_dir()).context("Failed to create lunik home dir")?;
 
-    // Copy the current executable to the bin dir
+    // Copy the current executable to the bin dir, unless we are running from there
     let self_path = std::env::current_exe()?;
     let self_name = self_path.file_name().unwrap();
     let self_bin_path = moon_bin_dir().join(self_name);
-    std::fs::copy(&self_path, &self_bin_path).context("Failed to copy self to bin dir")?;
+    let same_file = self_bin_path
+        .canonicalize()
+        .is_ok_and(|p| self_path.canonicalize().is_ok_and(|s| s == p));
+    if !same_file {
+        // Remove the old file first, in case it is a symlink to ourselves
+        if self_bin_path.is_symlink() {
+            std::fs::remove_file(&self_bin_path).context("Failed to remove old lunik")?;
+        }
+        std::fs::copy(&self_path, &self_bin_path).context("Failed to copy self to bin dir")?;
+    }
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
         let mut perms = self_bin_path.metadata()?.permissions();
         perms.set_mode(0o755);
-        std::fs::set_permissions(self_bin_path, perms)?;
+        std::fs::set_permissions(&self_bin_path, perms)?;
     }
 
     // Init config
     super::handle_init_config(true).context("Failed to init config")?;
+    if cmd.bin_dir.is_some() {
+        let mut config = read_user_config()?;
+        config.bin_dir = Some(moon_bin_dir());
+        save_config(&config).context("Unable to save configuration")?;
+    }
+
+    // Install the requested toolchains and set the default
+    for toolchain in &cmd.toolchains {
+        super::channel::add_channel(toolchain, true)
+            .with_context(|| format!("Failed to install toolchain {}", toolchain))?;
+    }
+    if let Some(default) = &cmd.default {
+        let mut config = read_user_config()?;
+        super::channel::set_default(&mut config, default)?;
+        save_config(&config).context("Unable to save configuration")?;
+        println!("Default toolchain set to {}", default);
+    }
+    if !cmd.toolchains.is_empty() || cmd.default.is_some() {
+        super::channel::sync_shims(&read_config()?).context("Failed to link shims")?;
+    }
 
     // Ask the user to add the bin dir to PATH
-    let shell = std::env::var("SHELL");
-    let shell = cmd.shell.clone().or(shell.ok()).and_then(|s| to_shell(&s));
+    let shell = match &cmd.shell {
+        Some(s) => Some(to_shell(s).with_context(|| format!("Unsupported shell: {}", s))?),
+        None => detect_shell(),
+    };
     let path = moon_bin_dir();
+    let rc_content = shell.map(|shell| {
+        let mut content = shell_rc_content(shell, &path);
+        if cmd.hook {
+            match shell_hook_content(shell, &self_bin_path) {
+                Some(hook) => content.push_str(&hook),
+                None => eprintln!("Shell hook is not supported for {:?}, skipping", shell),
+            }
+        }
+        content
+    });
 
-    let auto =
-        shell.is_some() && (cmd.auto || (!cmd.no_auto && prompt_user_if_they_want_to_auto_edit()?));
+    let interactive = !cmd.yes && std::io::stdin().is_terminal();
+    let auto = shell.is_some()
+        && !cmd.no_auto
+        && (cmd.auto || cmd.yes || (interactive && prompt_user_if_they_want_to_auto_edit()?));
     let mut auto_failed = false;
     if auto {
         let shell = shell.expect("Should not be None if auto is true");
-        auto_failed = edit_shell_rc(shell, &path).is_err();
+        let rc_content = rc_content.as_deref().unwrap_or_default();
+        auto_failed = edit_shell_rc(shell, rc_content).is_err();
     }
     if auto_failed || !auto {
-        prompt_user_to_manually_edit(shell, &path, auto_failed);
+        prompt_user_to_manually_edit(shell, rc_content.as_deref(), &path, auto_failed);
     }
 
     Ok(())
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Shell {
     Bash,
     Zsh,
     Fish,
+    /// POSIX sh and compatible shells, like dash and ksh
+    Sh,
+    Powershell,
+    Nushell,
+    Elvish,
+    Xonsh,
 }
 
+impl Shell {
+    const ALL: &'static [Shell] = &[
+        Shell::Bash,
+        Shell::Zsh,
+        Shell::Fish,
+        Shell::Sh,
+        Shell::Powershell,
+        Shell::Nushell,
+        Shell::Elvish,
+        Shell::Xonsh,
+    ];
+}
+
+/// Get the shell from its name or path, like `/bin/zsh` or `pwsh.exe`.
 fn to_shell(s: &str) -> Option<Shell> {
-    match s {
+    let name = Path::new(s.trim()).file_name()?.to_string_lossy();
+    let name = name.strip_suffix(".exe").unwrap_or(&name);
+    // Login shells are started with a leading `-`, like `-bash`
+    let name = name.strip_prefix('-').unwrap_or(name);
+    match name {
         "bash" => Some(Shell::Bash),
         "zsh" => Some(Shell::Zsh),
         "fish" => Some(Shell::Fish),
+        "sh" | "dash" | "ash" | "ksh" | "mksh" => Some(Shell::Sh),
+        "pwsh" | "powershell" => Some(Shell::Powershell),
+        "nu" => Some(Shell::Nushell),
+        "elvish" => Some(Shell::Elvish),
+        "xonsh" => Some(Shell::Xonsh),
         _ => None,
     }
 }
 
+/// Detect the user's shell from `$SHELL`, or from the parent process if it is
+/// not set. `$SHELL` comes first, since `lunik init` is often run by an
+/// installer script in a different shell.
+fn detect_shell() -> Option<Shell> {
+    match std::env::var("SHELL") {
+        Ok(shell) if !shell.is_empty() => to_shell(&shell),
+        _ => parent_process_name().and_then(|name| to_shell(&name)),
+    }
+}
+
+/// Get the executable name of the parent process.
+#[cfg(unix)]
+fn parent_process_name() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    if let Ok(comm) = std::fs::read_to_string(format!("/proc/{}/comm", ppid)) {
+        return Some(comm.trim().to_string());
+    }
+    // No procfs, e.g. on macOS
+    let output = std::process::Command::new("ps")
+        .args(["-o", "comm=", "-p"])
+        .arg(ppid.to_string())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(not(unix))]
+fn parent_process_name() -> Option<String> {
+    // PowerShell sets this variable, while cmd.exe doesn't
+    std::env::var_os("PSModulePath").map(|_| "pwsh".to_string())
+}
+
+/// The user's configuration directory, following the conventions most shells use.
+fn user_config_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    let home = home::home_dir().unwrap_or_default();
+    if cfg!(windows) {
+        std::env::var_os("APPDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join("AppData/Roaming"))
+    } else {
+        home.join(".config")
+    }
+}
+
+/// Ask PowerShell where its profile is, falling back to the default location.
+fn powershell_profile_path() -> PathBuf {
+    for exe in ["pwsh", "powershell"] {
+        let output = std::process::Command::new(exe)
+            .args(["-NoProfile", "-Command", "$PROFILE"])
+            .output();
+        if let Ok(output) = output {
+            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !path.is_empty() {
+                return PathBuf::from(path);
+            }
+        }
+    }
+    default_powershell_profile_path()
+}
+
+/// The default location of the PowerShell profile.
+fn default_powershell_profile_path() -> PathBuf {
+    let home = home::home_dir().unwrap_or_default();
+    if cfg!(windows) {
+        home.join("Documents/PowerShell/Microsoft.PowerShell_profile.ps1")
+    } else {
+        user_config_dir().join("powershell/Microsoft.PowerShell_profile.ps1")
+    }
+}
+
+/// Get the nushell config directory, where `env.nu` is.
+fn nushell_config_dir() -> PathBuf {
+    if cfg!(target_os = "macos") && std::env::var_os("XDG_CONFIG_HOME").is_none() {
+        home::home_dir()
+            .unwrap_or_default()
+            .join("Library/Application Support/nushell")
+    } else {
+        user_config_dir().join("nushell")
+    }
+}
+
 fn shell_rc_path(shell: Shell) -> PathBuf {
-    let home = home_dir();
+    let home = home::home_dir().unwrap_or_default();
     match shell {
-        Shell::Bash => home.join(".bashrc"),
+        Shell::Bash => {
+            // Terminals on macOS start login shells, which don't read `.bashrc`
+            let bash_profile = home.join(".bash_profile");
+            if cfg!(target_os = "macos")
+                || (bash_profile.exists() && !home.join(".bashrc").exists())
+            {
+                bash_profile
+            } else {
+                home.join(".bashrc")
+            }
+        }
         Shell::Zsh => home.join(".zshrc"),
-        Shell::Fish => home.join(".config/fish/config.fish"),
+        Shell::Fish => user_config_dir().join("fish/config.fish"),
+        Shell::Sh => home.join(".profile"),
+        Shell::Powershell => powershell_profile_path(),
+        Shell::Nushell => nushell_config_dir().join("env.nu"),
+        Shell::Elvish => user_config_dir().join("elvish/rc.elv"),
+        Shell::Xonsh => home.join(".xonshrc"),
+    }
+}
+
+/// The list of rc files edited by lunik, in the lunik dir. The PowerShell
+/// profile in particular may be moved, so undoing can't rely on finding it.
+const EDITED_RC_LIST: &str = "edited-shell-rc";
+
+/// Read the list of edited rc files, one path per line.
+fn read_edited_rc_list(list: &Path) -> Vec<PathBuf> {
+    std::fs::read_to_string(list)
+        .map(|content| {
+            content
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Add an rc file to the list of edited rc files.
+fn record_edited_rc(list: &Path, rc_path: &Path) -> anyhow::Result<()> {
+    let mut paths = read_edited_rc_list(list);
+    if paths.iter().any(|path| path == rc_path) {
+        return Ok(());
+    }
+    paths.push(rc_path.to_path_buf());
+    let content = paths
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect::<String>();
+    if let Some(parent) = list.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(list, content).with_context(|| format!("Failed to write {}", list.display()))
+}
+
+/// All rc files lunik might have edited, for undoing: those in the list of
+/// edited rc files, and the default rc files of all shells.
+fn all_shell_rc_paths(list: &Path) -> Vec<PathBuf> {
+    let mut paths = read_edited_rc_list(list);
+    let defaults = Shell::ALL
+        .iter()
+        .map(|&shell| match shell {
+            // Don't start PowerShell just to look for the lunik block
+            Shell::Powershell => default_powershell_profile_path(),
+            shell => shell_rc_path(shell),
+        })
+        .collect::<Vec<_>>();
+    let home = home::home_dir().unwrap_or_default();
+    for path in defaults
+        .into_iter()
+        .chain([home.join(".bashrc"), home.join(".bash_profile")])
+    {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
     }
+    paths
 }
 
 fn shell_rc_content(shell: Shell, path: &Path) -> String {
     match shell {
-        Shell::Bash | Shell::Zsh => format!("export PATH=\"{}:$PATH\"\n", path.display()),
+        Shell::Bash | Shell::Zsh | Shell::Sh => {
+            format!("export PATH=\"{}:$PATH\"\n", path.display())
+        }
         Shell::Fish => format!("set -gx PATH {} $PATH\n", path.display()),
+        Shell::Powershell => format!(
+            "$env:PATH = {} + [IO.Path]::PathSeparator + $env:PATH\n",
+            quote_powershell(&path.to_string_lossy())
+        ),
+        Shell::Nushell => format!(
+            "$env.PATH = ($env.PATH | split row (char esep) | prepend {})\n",
+            quote_nu(&path.to_string_lossy())
+        ),
+        Shell::Elvish => format!(
+            "set paths = [{} $@paths]\n",
+            quote_elvish(&path.to_string_lossy())
+        ),
+        Shell::Xonsh => format!(
+            "$PATH.insert(0, {})\n",
+            quote_python(&path.to_string_lossy())
+        ),
     }
 }
 
-fn edit_shell_rc(shell: Shell, path: &Path) -> anyhow::Result<()> {
-    let rc_path = shell_rc_path(shell);
-    let rc_content = shell_rc_content(shell, path);
+/// Quote a string for nushell, using a raw string.
+fn quote_nu(s: &str) -> String {
+    let hashes = "#".repeat(s.matches('#').count() + 1);
+    format!("r{hashes}'{s}'{hashes}")
+}
 
-    let rc_content = Cow::Borrowed(&rc_content);
-    let rc_content = rc_content.as_bytes();
+/// Quote a string for elvish.
+fn quote_elvish(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
 
-    let mut file = std::fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(&rc_path)
-        .context("Failed to open shell rc file")?;
+/// Quote a string for xonsh (Python).
+fn quote_python(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
 
-    // Append `# moonbit lunik` to the shell rc file
-    file.write_all(b"# moonbit lunik\n")
-        .context("Failed to write to shell rc file")?;
+/// Generate the hook that exports the environment of the current directory's
+/// toolchain whenever the directory changes.
+fn shell_hook_content(shell: Shell, lunik_path: &Path) -> Option<String> {
+    let lunik_path = lunik_path.to_string_lossy();
+    let hook = match shell {
+        Shell::Bash => format!(
+            "\
+_lunik_hook() {{
+  local previous_exit_status=$?
+  eval \"$({lunik} env --hook --shell bash 2>/dev/null)\"
+  return $previous_exit_status
+}}
+if [[ \";${{PROMPT_COMMAND[*]:-}};\" != *\";_lunik_hook;\"* ]]; then
+  PROMPT_COMMAND=\"_lunik_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}\"
+fi
+",
+            lunik = quote_posix(&lunik_path)
+        ),
+        Shell::Zsh => format!(
+            "\
+_lunik_hook() {{
+  eval \"$({lunik} env --hook --shell zsh 2>/dev/null)\"
+}}
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _lunik_hook
+_lunik_hook
+",
+            lunik = quote_posix(&lunik_path)
+        ),
+        Shell::Fish => format!(
+            "\
+function _lunik_hook --on-variable PWD
+    {lunik} env --hook --shell fish 2>/dev/null | source
+end
+_lunik_hook
+",
+            lunik = quote_fish(&lunik_path)
+        ),
+        Shell::Sh | Shell::Powershell | Shell::Nushell | Shell::Elvish | Shell::Xonsh => {
+            return None
+        }
+    };
+    Some(hook)
+}
+
+/// Marks the start of the lines managed by lunik in shell rc files.
+const RC_BLOCK_START: &str = "# >>> lunik >>>";
+/// Marks the end of the lines managed by lunik in shell rc files.
+const RC_BLOCK_END: &str = "# <<< lunik <<<";
+/// The marker used by older versions of lunik, followed by a single line.
+const RC_LEGACY_MARKER: &str = "# moonbit lunik";
+
+/// Wrap the content in the lunik block markers.
+fn rc_block(content: &str) -> String {
+    let content = content.trim_end_matches('\n');
+    format!("{}\n{}\n{}\n", RC_BLOCK_START, content, RC_BLOCK_END)
+}
+
+/// Remove the lunik block (and lines added by older versions) from the rc
+/// file content. Returns the new content and the position the block was at.
+/// Fails if a block has no end marker, rather than guessing where it ends.
+fn strip_rc_block(rc: &str) -> anyhow::Result<(String, Option<usize>)> {
+    let mut out = String::with_capacity(rc.len());
+    let mut position = None;
+    let mut lines = rc.split_inclusive('\n').enumerate();
+    while let Some((index, line)) = lines.next() {
+        let trimmed = line.trim_end();
+        if trimmed == RC_BLOCK_START {
+            position.get_or_insert(out.len());
+            if !lines
+                .by_ref()
+                .any(|(_, line)| line.trim_end() == RC_BLOCK_END)
+            {
+                anyhow::bail!(
+                    "The lunik block starting at line {} has no end marker `{}`",
+                    index + 1,
+                    RC_BLOCK_END
+                );
+            }
+        } else if trimmed == RC_LEGACY_MARKER {
+            position.get_or_insert(out.len());
+            lines.next();
+        } else {
+            out.push_str(line);
+        }
+    }
+    Ok((out, position))
+}
 
-    file.write_all(rc_content)
-        .context("Failed to write to shell rc file")?;
+/// Put the lunik block into the rc file content, replacing the existing one in place.
+fn replace_rc_block(rc: &str, content: &str) -> anyhow::Result<String> {
+    let (mut out, position) = strip_rc_block(rc)?;
+    let block = rc_block(content);
+    match position {
+        Some(pos) => out.insert_str(pos, &block),
+        None => {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&block);
+        }
+    }
+    Ok(out)
+}
 
+/// Copy the rc file to a backup next to it before editing. An existing backup
+/// is kept, so that it still holds the file from before lunik first edited it.
+fn backup_rc(rc_path: &Path) -> anyhow::Result<()> {
+    let mut backup_path = rc_path.as_os_str().to_owned();
+    backup_path.push(".lunik-backup");
+    if Path::new(&backup_path).exists() {
+        return Ok(());
+    }
+    std::fs::copy(rc_path, &backup_path).with_context(|| {
+        format!(
+            "Failed to back up shell rc file to {}",
+            Path::new(&backup_path).display()
+        )
+    })?;
+    Ok(())
+}
+
+fn edit_shell_rc(shell: Shell, rc_content: &str) -> anyhow::Result<()> {
+    let rc_path = shell_rc_path(shell);
+
+    let existing = if rc_path.exists() {
+        let existing = std::fs::read_to_string(&rc_path).context("Failed to read shell rc file")?;
+        backup_rc(&rc_path)?;
+        existing
+    } else {
+        if let Some(parent) = rc_path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create shell rc dir")?;
+        }
+        String::new()
+    };
+
+    let new_content = replace_rc_block(&existing, rc_content).with_context(|| {
+        format!(
+            "Unable to update {}, please edit it by hand",
+            rc_path.display()
+        )
+    })?;
+    std::fs::write(&rc_path, new_content).context("Failed to write to shell rc file")?;
+    println!("Updated {}", rc_path.display());
+    record_edited_rc(&lunik_dir().join(EDITED_RC_LIST), &rc_path)?;
+
+    Ok(())
+}
+
+/// Remove the lunik block from the rc files of all supported shells.
+pub fn undo_shell_rc() -> anyhow::Result<()> {
+    for rc_path in all_shell_rc_paths(&lunik_dir().join(EDITED_RC_LIST)) {
+        let Ok(existing) = std::fs::read_to_string(&rc_path) else {
+            continue;
+        };
+        let (new_content, position) = match strip_rc_block(&existing) {
+            Ok(stripped) => stripped,
+            Err(e) => {
+                eprintln!(
+                    "Leaving {} unchanged, please remove the lunik lines by hand: {}",
+                    rc_path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        if position.is_none() {
+            continue;
+        }
+        backup_rc(&rc_path)?;
+        std::fs::write(&rc_path, new_content)
+            .with_context(|| format!("Failed to write to {}", rc_path.display()))?;
+        println!("Removed lunik lines from {}", rc_path.display());
+    }
     Ok(())
 }
 
@@ -128,16 +594,21 @@ fn prompt_user_if_they_want_to_auto_edit() -> anyhow::Result<bool> {
         .map_err(|e| e.into())
 }
 
-fn prompt_user_to_manually_edit(shell: Option<Shell>, path: &Path, auto_edit_failed: bool) {
-    match shell {
-        Some(shell) => {
+fn prompt_user_to_manually_edit(
+    shell: Option<Shell>,
+    rc_content: Option<&str>,
+    path: &Path,
+    auto_edit_failed: bool,
+) {
+    match shell.zip(rc_content) {
+        Some((shell, rc_content)) => {
             if auto_edit_failed {
                 println!("We have failed to automatically edit your shell rc file.\n");
             }
             println!(
-                "Please manually add the following line to {}:\n\n    {}",
+                "Please manually add the following lines to {}:\n\n{}",
                 shell_rc_path(shell).display(),
-                shell_rc_content(shell, path)
+                rc_block(rc_content)
             );
         }
         None => {
@@ -151,3 +622,142 @@ fn prompt_user_to_manually_edit(shell: Option<Shell>, path: &Path, auto_edit_fai
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_shell() {
+        assert_eq!(to_shell("/bin/zsh"), Some(Shell::Zsh));
+        assert_eq!(to_shell("/usr/local/bin/fish"), Some(Shell::Fish));
+        assert_eq!(to_shell("-bash"), Some(Shell::Bash));
+        assert_eq!(to_shell("dash"), Some(Shell::Sh));
+        assert_eq!(to_shell("pwsh.exe"), Some(Shell::Powershell));
+        assert_eq!(to_shell("nu"), Some(Shell::Nushell));
+        assert_eq!(to_shell("/usr/bin/elvish"), Some(Shell::Elvish));
+        assert_eq!(to_shell("xonsh"), Some(Shell::Xonsh));
+        assert_eq!(to_shell("cmd.exe"), None);
+    }
+
+    #[test]
+    fn test_shell_rc_content() {
+        let path = Path::new("/home/u/.moon/bin");
+        let content = |shell| shell_rc_content(shell, path);
+        assert_eq!(
+            content(Shell::Bash),
+            "export PATH=\"/home/u/.moon/bin:$PATH\"\n"
+        );
+        assert_eq!(content(Shell::Zsh), content(Shell::Bash));
+        assert_eq!(content(Shell::Sh), content(Shell::Bash));
+        assert_eq!(
+            content(Shell::Fish),
+            "set -gx PATH /home/u/.moon/bin $PATH\n"
+        );
+        assert_eq!(
+            content(Shell::Powershell),
+            "$env:PATH = '/home/u/.moon/bin' + [IO.Path]::PathSeparator + $env:PATH\n"
+        );
+        assert_eq!(
+            content(Shell::Nushell),
+            "$env.PATH = ($env.PATH | split row (char esep) | prepend r#'/home/u/.moon/bin'#)\n"
+        );
+        assert_eq!(
+            content(Shell::Elvish),
+            "set paths = ['/home/u/.moon/bin' $@paths]\n"
+        );
+        assert_eq!(
+            content(Shell::Xonsh),
+            "$PATH.insert(0, '/home/u/.moon/bin')\n"
+        );
+    }
+
+    #[test]
+    fn test_shell_rc_content_quoting() {
+        let path = Path::new("/o'neil/#/bin");
+        assert_eq!(
+            shell_rc_content(Shell::Nushell, path),
+            "$env.PATH = ($env.PATH | split row (char esep) | prepend r##'/o'neil/#/bin'##)\n"
+        );
+        assert_eq!(
+            shell_rc_content(Shell::Elvish, path),
+            "set paths = ['/o''neil/#/bin' $@paths]\n"
+        );
+        assert_eq!(
+            shell_rc_content(Shell::Xonsh, path),
+            "$PATH.insert(0, '/o\\'neil/#/bin')\n"
+        );
+        assert_eq!(
+            shell_rc_content(Shell::Powershell, path),
+            "$env:PATH = '/o''neil/#/bin' + [IO.Path]::PathSeparator + $env:PATH\n"
+        );
+    }
+
+    #[test]
+    fn test_shell_hook_content() {
+        let lunik = Path::new("/home/u/.moon/bin/lunik");
+        assert!(shell_hook_content(Shell::Bash, lunik)
+            .unwrap()
+            .contains("eval \"$('/home/u/.moon/bin/lunik' env --hook --shell bash 2>/dev/null)\""));
+        assert!(shell_hook_content(Shell::Zsh, lunik)
+            .unwrap()
+            .contains("add-zsh-hook chpwd _lunik_hook"));
+        assert!(shell_hook_content(Shell::Fish, lunik)
+            .unwrap()
+            .contains("function _lunik_hook --on-variable PWD"));
+        assert!(shell_hook_content(Shell::Sh, lunik).is_none());
+    }
+
+    #[test]
+    fn test_replace_rc_block_appends() {
+        let rc = "alias ll='ls -l'";
+        assert_eq!(
+            replace_rc_block(rc, "export A=1\n").unwrap(),
+            "alias ll='ls -l'\n# >>> lunik >>>\nexport A=1\n# <<< lunik <<<\n"
+        );
+    }
+
+    #[test]
+    fn test_replace_rc_block_is_idempotent() {
+        let rc = "a\n# >>> lunik >>>\nexport A=1\n# <<< lunik <<<\nb\n";
+        let replaced = replace_rc_block(rc, "export A=2\n").unwrap();
+        assert_eq!(
+            replaced,
+            "a\n# >>> lunik >>>\nexport A=2\n# <<< lunik <<<\nb\n"
+        );
+        assert_eq!(
+            replace_rc_block(&replaced, "export A=2\n").unwrap(),
+            replaced
+        );
+    }
+
+    #[test]
+    fn test_strip_rc_block() {
+        let rc = "a\n# moonbit lunik\nexport PATH=x\nb\n# >>> lunik >>>\nc\n# <<< lunik <<<\n";
+        let (stripped, position) = strip_rc_block(rc).unwrap();
+        assert_eq!(stripped, "a\nb\n");
+        assert_eq!(position, Some(2));
+
+        // Nothing after a block without an end marker is removed
+        assert!(strip_rc_block("a\n# >>> lunik >>>\nc\nd\n").is_err());
+    }
+
+    #[test]
+    fn test_edited_rc_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("lunik").join(EDITED_RC_LIST);
+        // Like a profile redirected to OneDrive
+        let profile = dir
+            .path()
+            .join("OneDrive/Documents/PowerShell/Microsoft.PowerShell_profile.ps1");
+        assert_ne!(profile, default_powershell_profile_path());
+        assert!(!all_shell_rc_paths(&list).contains(&profile));
+
+        record_edited_rc(&list, &profile).unwrap();
+        record_edited_rc(&list, &profile).unwrap();
+        assert_eq!(read_edited_rc_list(&list), std::slice::from_ref(&profile));
+        let paths = all_shell_rc_paths(&list);
+        assert_eq!(paths.iter().filter(|path| **path == profile).count(), 1);
+        assert!(paths.contains(&default_powershell_profile_path()));
+    }
+}
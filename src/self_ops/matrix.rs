@@ -0,0 +1,235 @@
+//! Running a command on multiple toolchains.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::config::{read_config, Config};
+
+/// Directory for the separate target directories of parallel runs.
+const MATRIX_TARGET_DIR: &str = "target/lunik-matrix";
+
+/// Run a command once per toolchain, and summarize the results.
+///
+/// Example: `lunik matrix --toolchains latest,bleeding -- moon test`
+#[derive(clap::Parser, Debug)]
+pub struct MatrixSubcommand {
+    /// The toolchains to run the command with, separated by commas.
+    #[clap(long, short, value_delimiter = ',', required = true)]
+    toolchains: Vec<String>,
+
+    /// Run the command for all toolchains at the same time. For `moon`, each
+    /// toolchain gets a separate target directory under `target/lunik-matrix`.
+    #[clap(long, short)]
+    parallel: bool,
+
+    /// The command to run
+    #[clap(last = true, required = true, num_args(1..))]
+    cmd_args: Vec<String>,
+}
+
+#[derive(Debug)]
+struct MatrixResult {
+    toolchain: String,
+    success: bool,
+    status: String,
+    duration: Duration,
+}
+
+/// Print every line from `reader` to `out`, prefixed with the toolchain name.
+fn forward_lines(reader: impl Read, prefix: &str, mut out: impl Write) {
+    for line in BufReader::new(reader).split(b'\n') {
+        let Ok(line) = line else {
+            break;
+        };
+        let mut buf = Vec::with_capacity(prefix.len() + line.len() + 1);
+        buf.extend_from_slice(prefix.as_bytes());
+        buf.extend_from_slice(&line);
+        buf.push(b'\n');
+        // Write the whole line at once, so parallel outputs don't interleave
+        let _ = out.write_all(&buf);
+    }
+}
+
+/// Global moon flags which take their value as the next argument.
+const GLOBAL_VALUE_FLAGS: &[&str] = &["-C", "--directory", "--source-dir", "--target-dir"];
+
+/// Where to insert `--target-dir` in the arguments after the executable:
+/// right after the subcommand, so it never ends up after a `--`.
+fn target_dir_position(args: &[String]) -> usize {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" {
+            return i;
+        }
+        if !arg.starts_with('-') {
+            return i + 1;
+        }
+        i += if GLOBAL_VALUE_FLAGS.contains(&arg) {
+            2
+        } else {
+            1
+        };
+    }
+    args.len()
+}
+
+fn build_command(
+    cfg: &Config,
+    toolchain: &str,
+    cmd_args: &[String],
+    separate_target_dir: bool,
+) -> anyhow::Result<Command> {
+    let mut cmd = Command::new(&cmd_args[0]);
+    if separate_target_dir {
+        let (subcommand, rest) = cmd_args[1..].split_at(target_dir_position(&cmd_args[1..]));
+        cmd.args(subcommand)
+            .arg("--target-dir")
+            .arg(std::path::Path::new(MATRIX_TARGET_DIR).join(toolchain))
+            .args(rest);
+    } else {
+        cmd.args(&cmd_args[1..]);
+    }
+    crate::mux::configure_cmd_environment(&mut cmd, Some(toolchain), cfg)?;
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    Ok(cmd)
+}
+
+fn run_one(
+    cfg: &Config,
+    toolchain: &str,
+    cmd_args: &[String],
+    separate_target_dir: bool,
+    prefix_width: usize,
+) -> MatrixResult {
+    let start = Instant::now();
+    let prefix = format!("[{:width$}] ", toolchain, width = prefix_width);
+
+    let result =
+        build_command(cfg, toolchain, cmd_args, separate_target_dir).and_then(|mut cmd| {
+            let mut child = cmd
+                .spawn()
+                .with_context(|| format!("Failed to spawn `{}`", cmd_args[0]))?;
+            let stdout = child.stdout.take().unwrap();
+            let stderr = child.stderr.take().unwrap();
+            std::thread::scope(|s| {
+                s.spawn(|| forward_lines(stdout, &prefix, std::io::stdout()));
+                s.spawn(|| forward_lines(stderr, &prefix, std::io::stderr()));
+            });
+            child.wait().context("Failed to wait for the command")
+        });
+
+    let (success, status) = match result {
+        Ok(status) if status.success() => (true, "passed".to_string()),
+        Ok(status) => (false, format!("failed ({})", status)),
+        Err(e) => {
+            eprintln!("{}{:#}", prefix, e);
+            (false, "error".to_string())
+        }
+    };
+
+    MatrixResult {
+        toolchain: toolchain.to_string(),
+        success,
+        status,
+        duration: start.elapsed(),
+    }
+}
+
+pub fn handle_matrix(_cli: &super::Cli, cmd: &MatrixSubcommand) -> anyhow::Result<()> {
+    let cfg = read_config()?;
+
+    // Resolve all toolchains before running anything
+    let toolchains = cmd
+        .toolchains
+        .iter()
+        .map(|name| super::find_toolchain(&cfg, name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let is_moon = std::path::Path::new(&cmd.cmd_args[0])
+        .file_stem()
+        .is_some_and(|stem| stem == "moon");
+    let separate_target_dir = cmd.parallel && is_moon;
+    let prefix_width = toolchains.iter().map(|t| t.len()).max().unwrap_or(0);
+
+    let results = if cmd.parallel {
+        std::thread::scope(|s| {
+            let handles = toolchains
+                .iter()
+                .map(|toolchain| {
+                    let cfg = &cfg;
+                    s.spawn(move || {
+                        run_one(
+                            cfg,
+                            toolchain,
+                            &cmd.cmd_args,
+                            separate_target_dir,
+                            prefix_width,
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("matrix run panicked"))
+                .collect::<Vec<_>>()
+        })
+    } else {
+        toolchains
+            .iter()
+            .map(|toolchain| run_one(&cfg, toolchain, &cmd.cmd_args, false, prefix_width))
+            .collect()
+    };
+
+    println!();
+    println!(
+        "{:width$}  {:>9}  RESULT",
+        "TOOLCHAIN",
+        "TIME",
+        width = prefix_width.max("TOOLCHAIN".len())
+    );
+    for r in &results {
+        println!(
+            "{:width$}  {:>8.1}s  {}",
+            r.toolchain,
+            r.duration.as_secs_f64(),
+            r.status,
+            width = prefix_width.max("TOOLCHAIN".len())
+        );
+    }
+
+    let failed = results.iter().filter(|r| !r.success).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} toolchains failed", failed, results.len());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn position(args: &str) -> usize {
+        let args = args
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        target_dir_position(&args)
+    }
+
+    #[test]
+    fn test_target_dir_position() {
+        assert_eq!(position("build"), 1);
+        assert_eq!(position("test -- x"), 1);
+        assert_eq!(position("-C dir build -- x"), 3);
+        assert_eq!(position("--directory=dir -q build"), 3);
+        assert_eq!(position("-q -- build"), 1);
+        assert_eq!(position(""), 0);
+    }
+}
@@ -0,0 +1,159 @@
+//! Summary of the active toolchain.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::{
+    config::{read_config, Config},
+    mux::{
+        default_toolchain_source, executable_entry, real_toolchain_name, resolve_toolchain,
+        try_get_core_lib, try_get_toolchain_home, user_core_override, ToolchainSource,
+    },
+};
+
+/// Show the toolchain in use, why it was chosen, and the environment it runs with.
+#[derive(clap::Parser, Debug)]
+pub struct ShowSubcommand {
+    /// Show this toolchain instead, as if specified with `+toolchain`.
+    toolchain: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ShowOutput {
+    /// The resolved toolchain name
+    toolchain: String,
+    /// Where the toolchain choice came from
+    source: &'static str,
+    /// The project config that chose the toolchain, if any
+    source_file: Option<PathBuf>,
+    /// The toolchain followed by its fallbacks
+    fallback_chain: Vec<String>,
+    /// The value of `MOON_HOME` for tools run with this toolchain
+    moon_home: Option<PathBuf>,
+    /// The value of `MOON_CORE_OVERRIDE` for tools run with this toolchain
+    moon_core_override: Option<PathBuf>,
+    /// Whether `MOON_CORE_OVERRIDE` is inherited from the current environment
+    moon_core_override_from_env: bool,
+    /// Versions reported by the installed tools
+    versions: Versions,
+}
+
+#[derive(Debug, Serialize)]
+struct Versions {
+    moon: Option<String>,
+    moonrun: Option<String>,
+}
+
+fn source_tag(source: &ToolchainSource) -> &'static str {
+    match source {
+        ToolchainSource::Arg => "arg",
+        ToolchainSource::Env => "env",
+        ToolchainSource::ProjectFile(_) => "project_file",
+        ToolchainSource::Override(_) => "override",
+        ToolchainSource::Default => "default",
+    }
+}
+
+/// Run a tool of the toolchain and capture the first line of its output.
+pub fn tool_version(
+    cfg: &Config,
+    toolchain: Option<&str>,
+    tool: &str,
+    arg: &str,
+) -> Option<String> {
+    let output = executable_entry(cfg, toolchain, tool)
+        .ok()?
+        .arg(arg)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+}
+
+pub fn handle_show(cli: &super::Cli, cmd: &ShowSubcommand) -> anyhow::Result<()> {
+    let cfg = read_config()?;
+
+    let (toolchain, source) = resolve_toolchain(cmd.toolchain.as_deref());
+    let source = match source {
+        ToolchainSource::Default => default_toolchain_source()?,
+        source => source,
+    };
+    let initial_name = toolchain.as_deref().unwrap_or(&cfg.default);
+    let real_name = real_toolchain_name(&cfg, initial_name)?.into_owned();
+
+    let fallback_chain = cfg
+        .toolchain_fallback_iter(initial_name)
+        .map(|(name, _)| name.into_owned())
+        .collect::<Vec<_>>();
+
+    let moon_home = try_get_toolchain_home(&cfg, toolchain.as_deref()).ok();
+    let core_from_env = user_core_override().filter(|_| toolchain.is_none());
+    let moon_core_override_from_env = core_from_env.is_some();
+    let moon_core_override = match core_from_env {
+        Some(path) => Some(PathBuf::from(path)),
+        None => try_get_core_lib(&cfg, toolchain.as_deref()).ok(),
+    };
+
+    let versions = Versions {
+        moon: tool_version(&cfg, toolchain.as_deref(), "moon", "version"),
+        moonrun: tool_version(&cfg, toolchain.as_deref(), "moonrun", "--version"),
+    };
+
+    let output = ShowOutput {
+        toolchain: real_name,
+        source: source_tag(&source),
+        source_file: match &source {
+            ToolchainSource::ProjectFile(path) => Some(path.clone()),
+            _ => None,
+        },
+        fallback_chain,
+        moon_home,
+        moon_core_override,
+        moon_core_override_from_env,
+        versions,
+    };
+
+    if cli.is_json() {
+        return super::print_json(&output);
+    }
+
+    let display_path = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .map_or("<not found>".to_string(), |p| p.display().to_string())
+    };
+    let display_version = |version: &Option<String>| {
+        version
+            .clone()
+            .unwrap_or_else(|| "<unavailable>".to_string())
+    };
+
+    println!("Toolchain:          {}", output.toolchain);
+    println!("Chosen by:          {}", source);
+    println!("Fallback chain:     {}", output.fallback_chain.join(" -> "));
+    println!("MOON_HOME:          {}", display_path(&output.moon_home));
+    println!(
+        "MOON_CORE_OVERRIDE: {}{}",
+        display_path(&output.moon_core_override),
+        if output.moon_core_override_from_env {
+            " (from environment)"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "moon version:       {}",
+        display_version(&output.versions.moon)
+    );
+    println!(
+        "moonrun version:    {}",
+        display_version(&output.versions.moonrun)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,274 @@
+//! Uninstalling lunik.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use super::gc::{dir_size, human_size};
+use crate::config::{config_path, home_dir, moon_bin_dir, LIB_DIR, LUNIK_DIR};
+
+#[derive(clap::Parser, Debug)]
+pub enum SelfCommandline {
+    /// Uninstall lunik, removing only the files it created.
+    Uninstall(UninstallSubcommand),
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct UninstallSubcommand {
+    /// Skip the confirmation prompt.
+    #[clap(short, long)]
+    yes: bool,
+}
+
+pub fn entry(cli: &super::Cli, cmd: &SelfCommandline) -> anyhow::Result<()> {
+    match cmd {
+        SelfCommandline::Uninstall(v) => handle_uninstall(cli, v),
+    }
+}
+
+/// Collect the files and directories created by lunik.
+fn owned_paths() -> anyhow::Result<Vec<PathBuf>> {
+    let self_exe = std::env::current_exe()?;
+    owned_paths_in(
+        &home_dir(),
+        &moon_bin_dir(),
+        &config_path(),
+        self_exe.file_name().unwrap(),
+    )
+}
+
+/// Collect the files and directories created by lunik in the given locations.
+fn owned_paths_in(
+    home: &Path,
+    bin_dir: &Path,
+    config_path: &Path,
+    self_name: &OsStr,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    // Toolchains and other lunik resources
+    let lunik_dir = home.join(LUNIK_DIR);
+    if lunik_dir.exists() {
+        paths.push(lunik_dir);
+    }
+
+    // Links to the default toolchain
+    for link in [home.join(LIB_DIR), home.join("bin/lsp-server.js")] {
+        if link.is_symlink() {
+            paths.push(link);
+        }
+    }
+
+    // Shims, and lunik itself
+    if bin_dir.is_dir() {
+        for entry in std::fs::read_dir(bin_dir)
+            .with_context(|| format!("Failed to read {}", bin_dir.display()))?
+        {
+            let path = entry?.path();
+            if paths.contains(&path) {
+                continue;
+            }
+            if super::is_lunik_shim(&path) || path.file_name() == Some(self_name) {
+                paths.push(path);
+            }
+        }
+    }
+
+    if config_path.exists() {
+        paths.push(config_path.to_path_buf());
+    }
+
+    // Backups left by config migrations, e.g. `lunik.v1.json`
+    if let (Some(config_dir), Some(stem)) = (
+        config_path.parent(),
+        config_path.file_stem().and_then(|stem| stem.to_str()),
+    ) {
+        if config_dir.is_dir() {
+            for entry in std::fs::read_dir(config_dir)
+                .with_context(|| format!("Failed to read {}", config_dir.display()))?
+            {
+                let path = entry?.path();
+                let is_backup = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix(stem)?.strip_prefix(".v"))
+                    .and_then(|name| name.strip_suffix(".json"))
+                    .is_some_and(|version| {
+                        !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit())
+                    });
+                if is_backup {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_symlink() {
+        // Directory symlinks need `remove_dir` on Windows
+        std::fs::remove_file(path).or_else(|_| std::fs::remove_dir(path))
+    } else if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Let `cmd.exe` remove the running executable after we exit, since Windows
+/// does not allow removing it while it runs.
+#[cfg(windows)]
+fn schedule_removal(path: &Path) -> std::io::Result<()> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+    std::process::Command::new("cmd")
+        .raw_arg(format!(
+            "/C ping 127.0.0.1 -n 3 > NUL & del /F /Q \"{}\"",
+            path.display()
+        ))
+        .creation_flags(CREATE_NO_WINDOW)
+        .spawn()
+        .map(|_| ())
+}
+
+/// Remove the directory if it is empty, returning whether it was removed.
+fn remove_dir_if_empty(path: &Path) -> bool {
+    let is_empty = std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none());
+    is_empty && std::fs::remove_dir(path).is_ok()
+}
+
+fn handle_uninstall(_cli: &super::Cli, cmd: &UninstallSubcommand) -> anyhow::Result<()> {
+    let paths = owned_paths()?;
+
+    println!("The following files created by lunik will be removed:");
+    for path in &paths {
+        println!("  {:>10}  {}", human_size(dir_size(path)), path.display());
+    }
+    println!("Lines added by lunik to shell rc files will also be removed.");
+
+    if !cmd.yes
+        && !inquire::Confirm::new("Uninstall lunik?")
+            .with_default(false)
+            .prompt()?
+    {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    super::init::undo_shell_rc().context("Failed to clean up shell rc files")?;
+
+    #[cfg(windows)]
+    let self_exe = std::env::current_exe()?.canonicalize()?;
+    let mut failed = vec![];
+    for path in &paths {
+        #[cfg(windows)]
+        if path.canonicalize().is_ok_and(|path| path == self_exe) {
+            match schedule_removal(path) {
+                Ok(()) => println!("{} will be removed after lunik exits", path.display()),
+                Err(e) => {
+                    eprintln!(
+                        "Failed to schedule the removal of {}: {}",
+                        path.display(),
+                        e
+                    );
+                    failed.push(path);
+                }
+            }
+            continue;
+        }
+        match remove_path(path) {
+            Ok(()) => tracing::info!("Removed {}", path.display()),
+            Err(e) => {
+                eprintln!("Failed to remove {}: {}", path.display(), e);
+                failed.push(path);
+            }
+        }
+    }
+
+    // Clean up directories left empty
+    let home = home_dir();
+    for dir in [home.join("bin"), moon_bin_dir(), home.clone()] {
+        if remove_dir_if_empty(&dir) {
+            tracing::info!("Removed empty directory {}", dir.display());
+        }
+    }
+
+    // Summarize what is left
+    if home.exists() {
+        let remaining = std::fs::read_dir(&home)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if !remaining.is_empty() {
+            println!(
+                "The following files in {} were not created by lunik and are kept:",
+                home.display()
+            );
+            for path in remaining {
+                println!("  {}", path.display());
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!("Some files failed to be removed");
+    }
+    println!("lunik has been uninstalled.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owned_paths() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().join(".moon");
+        let bin_dir = home.join("bin");
+        std::fs::create_dir_all(home.join("lunik/toolchain/latest/lib")).unwrap();
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        std::fs::write(home.join("lunik.json"), "{}").unwrap();
+        std::fs::write(home.join("lunik.v1.json"), "{}").unwrap();
+        std::fs::write(home.join("lunik.vx.json"), "{}").unwrap();
+        std::fs::write(home.join("notes.txt"), "").unwrap();
+        symlink(home.join("lunik/toolchain/latest/lib"), home.join("lib")).unwrap();
+        std::fs::write(bin_dir.join("lunik"), "").unwrap();
+        symlink(bin_dir.join("lunik"), bin_dir.join("moon")).unwrap();
+        symlink("/usr/bin/env", bin_dir.join("other-link")).unwrap();
+        std::fs::write(bin_dir.join("user-script"), "").unwrap();
+
+        let paths = owned_paths_in(
+            &home,
+            &bin_dir,
+            &home.join("lunik.json"),
+            OsStr::new("lunik"),
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            [
+                bin_dir.join("lunik"),
+                bin_dir.join("moon"),
+                home.join("lib"),
+                home.join("lunik"),
+                home.join("lunik.json"),
+                home.join("lunik.v1.json"),
+            ]
+        );
+    }
+}